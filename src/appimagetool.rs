@@ -0,0 +1,229 @@
+use crate::{diag, fetch, in_path, Failure};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read},
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Delay before the first retry, doubled for every further one
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// The delay stops growing after this many doublings
+const MAX_BACKOFF_DOUBLINGS: u32 = 6;
+/// Number of output lines kept around to report when appimagetool fails
+const OUTPUT_TAIL_LINES: usize = 20;
+/// How often a running appimagetool is checked against its timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type Tail = Arc<Mutex<VecDeque<String>>>;
+
+/// Pick the appimagetool to run: the installed one, unless it does not report the pinned
+/// `version`, or one downloaded into the user cache when `fetch` is set
+pub(crate) fn locate(
+    fetch: bool,
+    version: Option<&str>,
+    sha256: Option<&str>,
+    quiet: bool,
+) -> Result<PathBuf> {
+    let installed = PathBuf::from("appimagetool");
+    if in_path("appimagetool") {
+        let Some(version) = version else {
+            return Ok(installed);
+        };
+        let reported = installed_version()?;
+        if reports_version(&reported, version) {
+            return Ok(installed);
+        }
+        if !fetch {
+            return Err(anyhow!(
+                "appimagetool_version is {version} but the installed appimagetool reports {reported:?}, install that version or pass --fetch-appimagetool"
+            )
+            .context(Failure::MissingTool));
+        }
+        diag::note(format_args!(
+            "The installed appimagetool is not version {version}, using a downloaded one"
+        ));
+    } else if !fetch {
+        // Reported as missing once it is run
+        return Ok(installed);
+    }
+    fetch::appimagetool(version, sha256, quiet)
+        .context("Could not download appimagetool")
+        .context(Failure::MissingTool)
+}
+
+/// First line of the output of `appimagetool --version`, which some builds write to
+/// stderr
+fn installed_version() -> Result<String> {
+    let output = Command::new("appimagetool")
+        .arg("--version")
+        .output()
+        .context("Could not run appimagetool --version")
+        .context(Failure::MissingTool)?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text += &String::from_utf8_lossy(&output.stderr);
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// Whether the version output `reported` names `version` as one of its words, so 1.9.1
+/// does not match 1.9.10
+fn reports_version(reported: &str, version: &str) -> bool {
+    let version = version.trim_start_matches('v');
+    reported
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+        .any(|word| word.trim_start_matches('v') == version)
+}
+
+/// Run `command` to completion, killing it once `timeout` has passed.
+///
+/// stdout and stderr are still shown as they are written, but the last lines are kept
+/// so they can be included in the error when the tool fails or hangs.
+pub(crate) fn run(mut command: Command, timeout: Option<Duration>) -> Result<()> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Error occurred: make sure that appimagetool is installed")?;
+
+    let tail = Tail::default();
+    let readers = [
+        tee(
+            child
+                .stdout
+                .take()
+                .context("Could not capture appimagetool stdout")?,
+            &tail,
+            |line| diag::info(line),
+        ),
+        tee(
+            child
+                .stderr
+                .take()
+                .context("Could not capture appimagetool stderr")?,
+            &tail,
+            |line| eprintln!("{line}"),
+        ),
+    ];
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().context("Could not wait on appimagetool")? {
+            break status;
+        }
+        if let Some(timeout) = timeout {
+            if started.elapsed() >= timeout {
+                child.kill().context("Could not kill appimagetool")?;
+                child.wait().context("Could not wait on appimagetool")?;
+                // Not waiting on the readers here, anything appimagetool spawned may
+                // still be holding its output open.
+                bail!(
+                    "appimagetool did not finish within {}s and was killed\n{}",
+                    timeout.as_secs(),
+                    snapshot(&tail)
+                );
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+    if !status.success() {
+        bail!(
+            "appimagetool {}\n{}",
+            describe_exit(status),
+            snapshot(&tail)
+        );
+    }
+    Ok(())
+}
+
+/// Run the command built by `command` like [`run`], running it again up to `retries`
+/// times with a growing delay when it fails, e.g. because the runtime download flaked.
+/// A tool that is not installed fails right away, retrying cannot fix that.
+pub(crate) fn run_with_retries(
+    command: impl Fn() -> Command,
+    timeout: Option<Duration>,
+    retries: u32,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match run(command(), timeout) {
+            Err(e) if is_not_found(&e) => return Err(e.context(Failure::MissingTool)),
+            Err(e) if attempt < retries => {
+                let delay = RETRY_DELAY * 2u32.pow(attempt.min(MAX_BACKOFF_DOUBLINGS));
+                attempt += 1;
+                diag::warning(format_args!(
+                    "{}, retrying in {}s ({attempt}/{retries})",
+                    e.to_string().lines().next().unwrap_or_default(),
+                    delay.as_secs()
+                ));
+                thread::sleep(delay);
+            }
+            result => return result.context(Failure::Appimagetool),
+        }
+    }
+}
+
+/// Whether `error` comes from a tool that could not be started since it does not exist
+fn is_not_found(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// Echo every line read from `stream` and remember it in `tail`
+fn tee<R: Read + Send + 'static>(stream: R, tail: &Tail, echo: fn(&str)) -> JoinHandle<()> {
+    let tail = Arc::clone(tail);
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            echo(&line);
+            let mut tail = tail.lock().unwrap_or_else(|e| e.into_inner());
+            if tail.len() == OUTPUT_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+    })
+}
+
+fn snapshot(tail: &Tail) -> String {
+    let tail = tail.lock().unwrap_or_else(|e| e.into_inner());
+    tail.iter().cloned().collect::<Vec<_>>().join("\n")
+}
+
+fn describe_exit(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("failed with exit code {code}"),
+        (None, Some(signal)) => format!("was terminated by signal {signal}"),
+        (None, None) => "failed".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_tool_is_not_retried() {
+        let started = Instant::now();
+        let error = run_with_retries(|| Command::new("cargo-appimage-test-no-such-tool"), None, 3)
+            .unwrap_err();
+        assert!(started.elapsed() < RETRY_DELAY);
+        assert_eq!(
+            Failure::exit_code_of(&error),
+            Failure::MissingTool.exit_code()
+        );
+    }
+}
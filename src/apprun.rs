@@ -1,5 +1,16 @@
 use anyhow::Context;
-use std::fs;
+use cargo_appimage::{
+    diag,
+    runner_config::{self, RunnerConfig},
+};
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{ErrorKind, Read, Write},
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
 
 fn main() -> anyhow::Result<()> {
     let here_dir = std::env::current_exe()?;
@@ -7,10 +18,26 @@ fn main() -> anyhow::Result<()> {
         .parent()
         .with_context(|| format!("{} has no parent directory", &here_dir.display()))?;
     std::env::set_current_dir(parent)?;
+    let config = fs::read_to_string(parent.join(runner_config::FILE_NAME))
+        .map(|contents| RunnerConfig::parse(&contents))
+        .unwrap_or_default();
+    let lib_dir = match &config.lib_dir {
+        Some(lib_dir) => format!("{}/{lib_dir}/:", parent.display()),
+        None => String::new(),
+    };
     std::env::set_var(
         "LD_LIBRARY_PATH",
-        format!("{}/usr/lib/:{}/usr/lib/i386-linux-gnu/:{}/usr/lib/x86_64-linux-gnu/:{}/usr/lib32/:{}/usr/lib64/:{}/lib/:{}/lib/i386-linux-gnu/:{}/lib/x86_64-linux-gnu/:{}/lib32/:{}/lib64/{}", parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), if let Ok(ldlibpath) = std::env::var("LD_LIBRARY_PATH") { ":".to_string() + &ldlibpath } else { String::new() }),
+        format!("{lib_dir}{}/usr/lib/:{}/usr/lib/i386-linux-gnu/:{}/usr/lib/x86_64-linux-gnu/:{}/usr/lib32/:{}/usr/lib64/:{}/lib/:{}/lib/i386-linux-gnu/:{}/lib/x86_64-linux-gnu/:{}/lib32/:{}/lib64/{}", parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), parent.display(), if let Ok(ldlibpath) = std::env::var("LD_LIBRARY_PATH") { ":".to_string() + &ldlibpath } else { String::new() }),
     );
+    for (name, value) in &config.env {
+        std::env::set_var(
+            name,
+            value.replace("$APPDIR", &parent.display().to_string()),
+        );
+    }
+    if let Some(splash) = &config.splash {
+        std::env::set_var("CARGO_APPIMAGE_SPLASH", parent.join(splash));
+    }
     std::env::set_var(
         "PATH",
         format!(
@@ -33,23 +60,146 @@ fn main() -> anyhow::Result<()> {
         ),
     );
 
-    let Some(executable) = fs::read_dir(parent.join("usr/bin/"))?.next() else {
-        eprintln!("Error: Executable file not found");
-        return Ok(());
+    // AppImages with several binaries start the one named like the symlink the AppImage
+    // was started through, which the runtime passes in ARGV0
+    let bin_dir = parent.join("usr/bin");
+    let invoked = std::env::var("ARGV0").ok().and_then(|argv0| {
+        let name = argv0.rsplit('/').next()?.to_string();
+        bin_dir.join(&name).is_file().then_some(name)
+    });
+    let executable_name = match invoked.or(config.exec) {
+        Some(name) => name,
+        None => {
+            let Some(executable) = fs::read_dir(&bin_dir)?.next() else {
+                diag::error("Executable file not found");
+                return Ok(());
+            };
+            let file_name = executable?.file_name();
+            let Some(executable_name) = file_name.to_str() else {
+                diag::error("Failed to get executable name");
+                return Ok(());
+            };
+            executable_name.to_string()
+        }
     };
 
-    let file_name = executable?.file_name();
+    if config.error_dialog {
+        std::process::exit(run_reporting_errors(
+            &bin_dir.join(&executable_name),
+            &executable_name,
+        ));
+    }
+    let err = exec::execvp(bin_dir.join(executable_name), std::env::args());
+    diag::error(err);
+
+    Ok(())
+}
 
-    let Some(executable_name) = file_name.to_str() else {
-        eprintln!("Error: Failed to get executable name");
-        return Ok(());
+/// Bytes at the end of stderr kept for the log of a failed run
+const STDERR_TAIL: usize = 64 * 1024;
+/// Lines at the end of stderr shown in the error dialog
+const DIALOG_LINES: usize = 20;
+
+/// Run `executable` as a child instead of replacing the runner with it, passing stderr
+/// through while keeping its end. When it fails the end is written to
+/// `~/.cache/<name>/last-error.log` and shown with zenity or kdialog if one of them is
+/// installed. Returns the exit code to exit with.
+fn run_reporting_errors(executable: &Path, name: &str) -> i32 {
+    let mut args = std::env::args_os();
+    let mut command = Command::new(executable);
+    if let Some(arg0) = args.next() {
+        command.arg0(arg0);
+    }
+    let mut child = match command.args(args).stderr(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            diag::error(format_args!(
+                "Could not start {}: {e}",
+                executable.display()
+            ));
+            return 127;
+        }
+    };
+    let mut tail = VecDeque::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        let mut buf = [0; 8192];
+        loop {
+            match stderr.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = std::io::stderr().write_all(&buf[..n]);
+                    tail.extend(&buf[..n]);
+                    let excess = tail.len().saturating_sub(STDERR_TAIL);
+                    tail.drain(..excess);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    }
+    let status = match child.wait() {
+        Ok(status) => status,
+        Err(e) => {
+            diag::error(format_args!("Could not wait for {name}: {e}"));
+            return 1;
+        }
+    };
+    let (code, reason) = match (status.code(), status.signal()) {
+        (Some(0), _) => return 0,
+        (Some(code), _) => (code, format!("exited with code {code}")),
+        (None, Some(signal)) => (128 + signal, format!("was killed by signal {signal}")),
+        (None, None) => (1, "failed".to_string()),
     };
 
-    let err = exec::execvp(
-        parent.join(format!("usr/bin/{executable_name}")),
-        std::env::args(),
-    );
-    eprintln!("Error: {}", err);
+    let stderr = String::from_utf8_lossy(tail.make_contiguous()).into_owned();
+    let log = write_log(name, &reason, &stderr);
+    let mut message = format!("{name} {reason}.");
+    let lines: Vec<&str> = stderr.lines().collect();
+    if !lines.is_empty() {
+        message += "\n\n";
+        message += &lines[lines.len().saturating_sub(DIALOG_LINES)..].join("\n");
+    }
+    if let Some(log) = &log {
+        message += &format!("\n\nThe error output was saved to {}", log.display());
+    }
+    show_dialog(name, &message);
+    code
+}
 
-    Ok(())
+/// Write `stderr` of a failed run to the cache dir, returning the path of the log
+fn write_log(name: &str, reason: &str, stderr: &str) -> Option<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    let dir = cache.join(name);
+    fs::create_dir_all(&dir).ok()?;
+    let log = dir.join("last-error.log");
+    fs::write(&log, format!("{name} {reason}\n\n{stderr}")).ok()?;
+    Some(log)
+}
+
+/// Show `message` in an error dialog with the first of zenity or kdialog installed
+fn show_dialog(name: &str, message: &str) {
+    let title = format!("{name} stopped unexpectedly");
+    let dialogs = [
+        (
+            "zenity",
+            vec![
+                "--error",
+                "--no-markup",
+                "--title",
+                &title,
+                "--text",
+                message,
+            ],
+        ),
+        ("kdialog", vec!["--title", &title, "--error", message]),
+    ];
+    for (tool, args) in &dialogs {
+        match Command::new(tool).args(args).status() {
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            _ => return,
+        }
+    }
 }
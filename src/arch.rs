@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use std::{io::Read, path::Path};
+
+/// Target triple the binaries are built for: the configured one, `env` (the value of
+/// `CARGO_BUILD_TARGET`) or `None` for the host
+pub(crate) fn build_target(configured: Option<&str>, env: Option<String>) -> Option<String> {
+    configured
+        .map(str::to_string)
+        .or(env)
+        .filter(|target| !target.is_empty())
+}
+
+/// Architecture name appimagetool expects in `ARCH`: `configured` when set, otherwise the
+/// one of `target` as given by [`appimage_arch`]
+pub(crate) fn packaged_arch(configured: Option<&str>, target: Option<&str>) -> String {
+    configured.map_or_else(|| appimage_arch(target), str::to_string)
+}
+
+/// Architecture name appimagetool expects in `ARCH` for binaries built for `target`, the
+/// host architecture when `None`
+pub(crate) fn appimage_arch(target: Option<&str>) -> String {
+    let arch = match target {
+        Some(target) => target.split('-').next().unwrap_or(target),
+        None => platforms::target::TARGET_ARCH.as_str(),
+    };
+    match arch {
+        "i386" | "i586" | "i686" | "x86" => "i686",
+        "arm" | "armv7" | "armv7a" | "thumbv7neon" => "armhf",
+        "riscv64gc" => "riscv64",
+        arch => arch,
+    }
+    .to_string()
+}
+
+/// Architecture name in `ARCH` of the ELF binary at `path`, from the machine type in its
+/// header. `None` when it is not an ELF binary or the machine type is not known.
+pub(crate) fn elf_arch(path: &Path) -> Result<Option<&'static str>> {
+    let mut header = [0; 20];
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Cannot find binary file at {}", path.display()))?;
+    match file.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Could not read {}", path.display())),
+    }
+    if header[..4] != *b"\x7fELF" {
+        return Ok(None);
+    }
+    // e_machine follows the 16 byte ident, in the byte order given by EI_DATA
+    let machine = match header[5] {
+        2 => u16::from_be_bytes([header[18], header[19]]),
+        _ => u16::from_le_bytes([header[18], header[19]]),
+    };
+    Ok(match machine {
+        3 => Some("i686"),
+        40 => Some("armhf"),
+        62 => Some("x86_64"),
+        183 => Some("aarch64"),
+        243 => Some("riscv64"),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn appimage_arch_names() {
+        assert_eq!(appimage_arch(Some("x86_64-unknown-linux-gnu")), "x86_64");
+        assert_eq!(appimage_arch(Some("aarch64-unknown-linux-musl")), "aarch64");
+        assert_eq!(appimage_arch(Some("i686-unknown-linux-gnu")), "i686");
+        assert_eq!(
+            appimage_arch(Some("armv7-unknown-linux-gnueabihf")),
+            "armhf"
+        );
+        assert_eq!(
+            appimage_arch(Some("riscv64gc-unknown-linux-gnu")),
+            "riscv64"
+        );
+        assert_eq!(packaged_arch(Some("armhf"), Some("x86_64")), "armhf");
+    }
+
+    // Tests run in parallel, no other test may read or set CARGO_BUILD_TARGET
+    #[test]
+    fn cargo_build_target_locates_the_binaries() {
+        let target = "aarch64-unknown-linux-gnu";
+        let target_dir = std::env::temp_dir().join(format!(
+            "cargo-appimage-build-target-{}",
+            std::process::id()
+        ));
+        let release = target_dir.join(target).join("release");
+        std::fs::create_dir_all(&release).unwrap();
+        std::fs::write(release.join("app"), []).unwrap();
+        std::fs::set_permissions(release.join("app"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+
+        let from_env = build_target(None, Some(target.to_string()));
+        let configured = build_target(Some("x86_64-unknown-linux-gnu"), Some(target.to_string()));
+        let empty = build_target(None, Some(String::new()));
+
+        assert_eq!(from_env.as_deref(), Some(target));
+        assert_eq!(configured.as_deref(), Some("x86_64-unknown-linux-gnu"));
+        assert_eq!(empty, None);
+        assert_eq!(appimage_arch(from_env.as_deref()), "aarch64");
+        let executables = crate::cargo_build::existing(
+            &target_dir,
+            from_env.as_deref(),
+            None,
+            &["app".to_string()],
+        )
+        .unwrap();
+        assert_eq!(executables["app"], release.join("app"));
+        std::fs::remove_dir_all(&target_dir).unwrap();
+    }
+}
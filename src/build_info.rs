@@ -0,0 +1,87 @@
+//! Description of the build shipped in the AppImage, to tell builds apart when debugging
+
+use anyhow::{Context, Result};
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Write `usr/share/<name>/build-info.json` to `appdir` with the version, git commit,
+/// build date, rustc version and target triple of the build. The date is `epoch` for
+/// reproducible builds. Whatever cannot be found out is left `null`.
+pub(crate) fn write(
+    appdir: &Path,
+    name: &str,
+    version: &str,
+    target: Option<&str>,
+    epoch: Option<u64>,
+) -> Result<()> {
+    let rustc = rustc_version();
+    let host = rustc.as_deref().and_then(|rustc| {
+        rustc
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .map(str::to_string)
+    });
+    let epoch = epoch.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+    });
+    let info = serde_json::json!({
+        "name": name,
+        "version": version,
+        "git_commit": git_commit(),
+        "build_date": rfc3339(epoch),
+        "rustc": rustc.as_deref().and_then(|rustc| rustc.lines().next()),
+        "target": target.map(str::to_string).or(host),
+    });
+
+    let dir = appdir.join(format!("usr/share/{name}"));
+    fs_extra::dir::create_all(&dir, false)?;
+    let contents = serde_json::to_string_pretty(&info)? + "\n";
+    std::fs::write(dir.join("build-info.json"), contents).context("Error writing the build info")
+}
+
+/// Commit checked out in the package root, `None` outside of a git repository
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Output of `rustc -vV` for the rustc cargo builds with
+fn rustc_version() -> Option<String> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc)
+        .arg("-vV")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `epoch` as a UTC timestamp like `2024-01-31T12:00:00Z`
+fn rfc3339(epoch: u64) -> String {
+    let (days, secs) = (epoch / 86400, epoch % 86400);
+    // Civil date of a day count since 1970-01-01, after Howard Hinnant's civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
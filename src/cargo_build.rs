@@ -0,0 +1,164 @@
+use anyhow::{bail, Context, Result};
+use cargo_metadata::Message;
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// What `cargo build` produced
+#[derive(Debug, Default)]
+pub(crate) struct Built {
+    /// Executables by binary name
+    pub executables: BTreeMap<String, PathBuf>,
+    /// `OUT_DIR` of every package whose build script ran, by package id
+    pub out_dirs: BTreeMap<String, PathBuf>,
+}
+
+/// Run `cargo build` with `args` and return the executables it produced and the `OUT_DIR`
+/// of the build scripts.
+///
+/// The paths come from cargo's artifact messages, so custom profiles, target dirs and
+/// cross targets all end up where cargo put them. Diagnostics are still rendered to the
+/// terminal, or passed through as JSON when `--message-format=json` was asked for.
+pub(crate) fn run(mut command: Command, args: &[String]) -> Result<Built> {
+    let (args, message_format) = split_message_format(args);
+    let json = message_format
+        .as_deref()
+        .is_some_and(|f| f.starts_with("json"));
+    let message_format = match message_format.as_deref() {
+        Some(format) if json => format.to_string(),
+        Some("short") => "json-render-diagnostics,json-diagnostic-short".to_string(),
+        _ => "json-render-diagnostics".to_string(),
+    };
+
+    let mut child = command
+        .args(args)
+        .arg(format!("--message-format={message_format}"))
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to build package")?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("Could not capture cargo build output")?;
+
+    let mut built = Built::default();
+    for line in BufReader::new(stdout).lines() {
+        let line = line.context("Could not read cargo build output")?;
+        if json {
+            println!("{line}");
+        }
+        match Message::parse_stream(line.as_bytes()).next() {
+            Some(Ok(Message::CompilerArtifact(artifact)))
+                if artifact.target.kind.iter().any(|k| k == "bin") =>
+            {
+                if let Some(executable) = artifact.executable {
+                    built
+                        .executables
+                        .insert(artifact.target.name, PathBuf::from(executable));
+                }
+            }
+            Some(Ok(Message::BuildScriptExecuted(script))) => {
+                built
+                    .out_dirs
+                    .insert(script.package_id.repr, PathBuf::from(script.out_dir));
+            }
+            Some(Ok(Message::TextLine(line))) if !json => println!("{line}"),
+            _ => {}
+        }
+    }
+
+    let status = child.wait().context("Failed to build package")?;
+    if !status.success() {
+        bail!("Failed to build package");
+    }
+    Ok(built)
+}
+
+/// Locate the already built executables of `bins` in `target_dir`, where cargo puts them
+/// when building for `target` with `profile`, the release profile when `None`
+pub(crate) fn existing(
+    target_dir: &Path,
+    target: Option<&str>,
+    profile: Option<&str>,
+    bins: &[String],
+) -> Result<BTreeMap<String, PathBuf>> {
+    // The built-in profiles output to the directory of the profile they inherit from
+    let profile_dir = match profile.unwrap_or("release") {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    };
+    let mut dir = target_dir.to_path_buf();
+    if let Some(target) = target {
+        dir.push(target);
+    }
+    dir.push(profile_dir);
+    let mut executables = BTreeMap::new();
+    for bin in bins {
+        let executable = dir.join(bin);
+        if !executable.is_file() {
+            let found = match executables_in(&dir) {
+                None => "does not exist".to_string(),
+                Some(found) if found.is_empty() => "has no executables".to_string(),
+                Some(found) => format!("has {}", found.join(", ")),
+            };
+            bail!(
+                "--no-build was passed but {} does not exist, build it first\n  \
+                 Looked for the {} profile and {} in {}, which {found}\n  \
+                 Was it built with another --profile or --target, into another CARGO_TARGET_DIR, or does it need required-features that were not enabled?",
+                executable.display(),
+                profile.unwrap_or("release"),
+                target.map_or("the host".to_string(), |target| format!("the target {target}")),
+                dir.display(),
+            );
+        }
+        executables.insert(bin.clone(), executable);
+    }
+    Ok(executables)
+}
+
+/// Names of the executable files in `dir`, `None` when it cannot be read
+fn executables_in(dir: &Path) -> Option<Vec<String>> {
+    let mut found: Vec<String> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry.metadata().is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            })
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    found.sort();
+    Some(found)
+}
+
+/// Whether `--message-format=json` is among the arguments passed on to cargo, cargo-appimage
+/// then reports what it created as JSON as well
+pub(crate) fn json_messages(args: &[String]) -> bool {
+    split_message_format(args)
+        .1
+        .is_some_and(|format| format.starts_with("json"))
+}
+
+/// Take the `--message-format` given by the user out of the cargo arguments, cargo only
+/// accepts a single one and artifact messages are always needed
+fn split_message_format(args: &[String]) -> (Vec<String>, Option<String>) {
+    let mut rest = vec![];
+    let mut message_format = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(format) = arg.strip_prefix("--message-format=") {
+            message_format = Some(format.to_string());
+        } else if arg == "--message-format" {
+            message_format = args.next().cloned();
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    (rest, message_format)
+}
@@ -0,0 +1,42 @@
+//! The freedesktop registry of desktop entry categories
+
+use crate::diag;
+use anyhow::{bail, Result};
+
+const CATEGORIES: &str = include_str!("categories");
+
+/// Check `categories` against the bundled registry. Unknown ones only get a warning since
+/// desktops ignore them, categories used without the ones they have to be combined with
+/// are an error. `X-` categories are vendor extensions and are not checked.
+pub(crate) fn check(categories: &[String]) -> Result<()> {
+    let registry: Vec<(&str, Vec<&str>)> = CATEGORIES
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?, fields.collect()))
+        })
+        .collect();
+    for category in categories.iter().filter(|c| !c.starts_with("X-")) {
+        let Some((_, related)) = registry.iter().find(|(name, _)| name == category) else {
+            diag::warning(format_args!(
+                "{category} is not a registered desktop entry category, prefix custom ones with X-"
+            ));
+            continue;
+        };
+        let combined = related.is_empty()
+            || related.iter().any(|alternative| {
+                alternative
+                    .split(';')
+                    .all(|needed| categories.iter().any(|c| c == needed))
+            });
+        if !combined {
+            bail!(
+                "The category {category} has to be combined with {}",
+                related.join(" or ").replace(';', " and ")
+            );
+        }
+    }
+    Ok(())
+}
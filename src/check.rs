@@ -0,0 +1,172 @@
+//! Validation of the packaging settings without building or writing anything
+
+use crate::{
+    categories, check_bin_name, desktop, diag, fuse, get_app_runner_binary_path,
+    get_manifest_from_path, icon, in_path, prebuilt_binary_name, selected_bins, AppImageConfig,
+    Failure, CARGO_FNAME,
+};
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+/// Check the settings of the package described by `config`, the files they refer to and
+/// the tools the build needs, reporting every problem found instead of stopping at the
+/// first one
+pub fn check_config(mut config: AppImageConfig) -> Result<()> {
+    let (path, meta) = get_manifest_from_path(&config.package_path).context(Failure::Config)?;
+    let path = path.canonicalize().context("Could not canonicalize path")?;
+    let root = path.parent().context("Package path has no parent")?;
+    let pkg = meta
+        .package
+        .context(format!("Cannot load metadata from {CARGO_FNAME}"))?;
+    let mut problems = vec![];
+
+    if let Err(e) = config.resolve_paths(root) {
+        problems.push(format!("{e:#}"));
+    }
+    let bins: Vec<String> = meta
+        .bin
+        .into_iter()
+        .map(|bin| bin.name.unwrap_or(pkg.name.clone()))
+        .collect();
+    for excluded in config.exclude_bins.iter().filter(|b| !bins.contains(b)) {
+        problems.push(format!(
+            "exclude_bins contains {excluded:?}, which is not a binary of this package"
+        ));
+    }
+    if config.include_bins.is_some() && !config.exclude_bins.is_empty() {
+        problems.push(
+            "bins lists every binary that is packaged, it cannot be combined with exclude_bins"
+                .to_string(),
+        );
+    }
+    for included in config
+        .include_bins
+        .iter()
+        .flatten()
+        .filter(|b| !bins.contains(b))
+    {
+        problems.push(format!(
+            "bins contains {included:?}, which is not a binary of this package"
+        ));
+    }
+    for name in config.bins.keys().filter(|name| !bins.contains(name)) {
+        problems.push(format!(
+            "[package.metadata.appimage.bin.{name}] does not match a binary of this package"
+        ));
+    }
+    let selected = selected_bins(&config.cargo_args);
+    let packaged: Vec<String> = match &config.binary {
+        Some(binary) => match prebuilt_binary_name(binary) {
+            Ok(name) => vec![name],
+            Err(e) => {
+                problems.push(format!("{e:#}"));
+                vec![]
+            }
+        },
+        None => bins
+            .iter()
+            .filter(|name| config.packages_bin(name))
+            .filter(|name| selected.is_empty() || selected.contains(name))
+            .cloned()
+            .collect(),
+    };
+    if config.combine_bins && packaged.len() > 1 {
+        match config.primary_bin.clone().or(pkg.default_run.clone()) {
+            Some(primary) if !packaged.contains(&primary) => problems.push(format!(
+                "The primary binary {primary} is not one of the packaged binaries"
+            )),
+            Some(_) => {}
+            None => problems.push("combine_bins needs primary_bin or default-run to pick the binary the desktop entry starts".to_string()),
+        }
+    }
+
+    // A missing appimagetool is downloaded with fetch_appimagetool
+    let mut tools = vec![];
+    if config.squashfs_only {
+        tools.push("mksquashfs");
+    } else if !config.fetch_appimagetool {
+        tools.push("appimagetool");
+    }
+    if !config.squashfs_only {
+        fuse::warn_if_missing();
+    }
+    if config.emit_tarball {
+        tools.push("tar");
+    }
+    for name in &packaged {
+        let mut bin_config = match config.for_bin(name) {
+            Ok(bin_config) => bin_config,
+            Err(e) => {
+                problems.push(format!("{e:#}"));
+                continue;
+            }
+        };
+        // Problems of the package settings are the same for every binary
+        for problem in check_bin(name, &mut bin_config, root) {
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+        if bin_config.auto_link {
+            tools.push("ldd");
+        }
+        if bin_config.strip {
+            tools.push("strip");
+        }
+        if bin_config.upx {
+            tools.push("upx");
+        }
+    }
+    tools.sort_unstable();
+    tools.dedup();
+    for tool in tools.iter().filter(|tool| !in_path(tool)) {
+        problems.push(format!("{tool} is not installed or not in PATH"));
+    }
+    if let Err(e) = get_app_runner_binary_path() {
+        problems.push(format!("{e:#}, reinstall cargo-appimage"));
+    }
+
+    for problem in &problems {
+        diag::error(problem);
+    }
+    match problems.len() {
+        0 => {
+            diag::success(format_args!("No problems found in {}", path.display()));
+            Ok(())
+        }
+        1 => Err(anyhow!("1 problem found in {}", path.display()).context(Failure::Config)),
+        n => Err(anyhow!("{n} problems found in {}", path.display()).context(Failure::Config)),
+    }
+}
+
+/// Problems with the settings `config` of the binary `name` in the package root `root`
+fn check_bin(name: &str, config: &mut AppImageConfig, root: &Path) -> Vec<String> {
+    let mut problems = vec![];
+    if let Err(e) = config.resolve_paths(root) {
+        problems.push(format!("{e:#}"));
+    }
+    if let Err(e) = check_bin_name(name, config.product_name.as_deref()) {
+        problems.push(format!("{e:#}"));
+    }
+    if let Some(icon) = config.icon.as_deref().filter(|icon| icon.is_file()) {
+        if let Err(e) = icon::png_size(icon) {
+            problems.push(format!("{e:#}"));
+        }
+    }
+    let exe_name = config.bin_name.as_deref().unwrap_or(name);
+    let desktop_entry = match config.desktop_file.as_deref().filter(|file| file.is_file()) {
+        Some(desktop_file) => std::fs::read_to_string(desktop_file)
+            .with_context(|| format!("Cannot read desktop file {}", desktop_file.display()))
+            .and_then(|contents| desktop::adapt(&contents, exe_name))
+            .with_context(|| format!("Invalid desktop file {}", desktop_file.display()))
+            .map(drop),
+        None if config.desktop_file.is_none() => {
+            categories::check(&config.categories).and_then(|()| desktop::check_terminal(config))
+        }
+        None => Ok(()),
+    };
+    if let Err(e) = desktop_entry {
+        problems.push(format!("{e:#}"));
+    }
+    problems
+}
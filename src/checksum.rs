@@ -0,0 +1,33 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{fmt::Write, fs::File, io, path::Path};
+
+/// Write `<file>.sha256` next to `file` in the format read by `sha256sum -c`
+pub(crate) fn write_sha256(file: &Path) -> Result<()> {
+    let mut line = sha256(file)?;
+    let file_name = file
+        .file_name()
+        .with_context(|| format!("No filename for {}", file.display()))?
+        .to_string_lossy();
+    line += &format!("  {file_name}\n");
+
+    let mut checksum_path = file.as_os_str().to_owned();
+    checksum_path.push(".sha256");
+    std::fs::write(&checksum_path, line)
+        .with_context(|| format!("Could not write {}", Path::new(&checksum_path).display()))
+}
+
+/// SHA-256 of the contents of `file` as lowercase hex
+pub(crate) fn sha256(file: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut reader =
+        File::open(file).with_context(|| format!("Could not open {}", file.display()))?;
+    io::copy(&mut reader, &mut hasher)
+        .with_context(|| format!("Could not read {}", file.display()))?;
+
+    let mut hex = String::new();
+    for byte in hasher.finalize() {
+        write!(hex, "{byte:02x}")?;
+    }
+    Ok(hex)
+}
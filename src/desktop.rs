@@ -0,0 +1,195 @@
+use crate::{diag, AppImageConfig};
+use anyhow::{bail, Result};
+
+/// Values of the desktop entry `Type` key defined by the freedesktop spec
+pub(crate) const TYPES: [&str; 3] = ["Application", "Link", "Directory"];
+
+/// Version of the desktop entry spec the generated entries follow
+pub(crate) const SPEC_VERSION: &str = "1.0";
+
+/// Placeholder for the executable in the `exec` template
+pub(crate) const EXEC_BIN: &str = "{bin}";
+
+/// Field codes the desktop entry spec allows in `Exec`, the others are deprecated
+const FIELD_CODES: [char; 8] = ['f', 'F', 'u', 'U', 'i', 'c', 'k', '%'];
+
+/// Render the `.desktop` file for the binary `name` of a package at `version`
+pub(crate) fn render(name: &str, version: &str, config: &AppImageConfig) -> String {
+    let mut entry = format!(
+        "[Desktop Entry]\nVersion={}\nName={}\nExec={}\nIcon=icon\nType={}\n",
+        config
+            .desktop_spec_version
+            .as_deref()
+            .unwrap_or(SPEC_VERSION),
+        config
+            .desktop_name
+            .as_deref()
+            .or(config.product_name.as_deref())
+            .unwrap_or(name),
+        config.exec.as_deref().unwrap_or(EXEC_BIN).replace(
+            EXEC_BIN,
+            if config.exec_use_apprun {
+                "AppRun"
+            } else {
+                config.bin_name.as_deref().unwrap_or(name)
+            }
+        ),
+        config.desktop_type.as_deref().unwrap_or("Application")
+    );
+    for (locale, name) in &config.name_localized {
+        entry += &format!("Name[{locale}]={name}\n");
+    }
+    if let Some(comment) = &config.comment {
+        entry += &format!("Comment={comment}\n");
+    }
+    for (locale, comment) in &config.comment_localized {
+        entry += &format!("Comment[{locale}]={comment}\n");
+    }
+    if !config.keywords.is_empty() {
+        entry += &format!("Keywords={};\n", config.keywords.join(";"));
+    }
+    for (locale, keywords) in &config.keywords_localized {
+        entry += &format!("Keywords[{locale}]={};\n", keywords.join(";"));
+    }
+    if config.terminal {
+        entry += "Terminal=true\n";
+    }
+    if config.no_display {
+        entry += "NoDisplay=true\n";
+    }
+    entry += &format!(
+        "X-AppImage-Name={}\nX-AppImage-Version={}\n",
+        config
+            .appimage_name
+            .as_deref()
+            .or(config.product_name.as_deref())
+            .unwrap_or(name),
+        config.appimage_version.as_deref().unwrap_or(version)
+    );
+    if config.integrate == Some(false) {
+        entry += "X-AppImage-Integrate=false\n";
+    }
+    if config.categories.is_empty() {
+        entry += "Categories=Utility;";
+    } else {
+        entry += &format!("Categories={};", config.categories.join(";"));
+    }
+    entry
+}
+
+/// Keys appimagetool needs in the `[Desktop Entry]` group of an existing desktop file
+const REQUIRED_KEYS: [&str; 4] = ["Type", "Name", "Exec", "Icon"];
+
+/// Prepare an existing desktop file for the executable `name`. The program run by `Exec`
+/// has to be the executable, and `Icon` is pointed at the icon in the root of the AppDir.
+pub(crate) fn adapt(contents: &str, name: &str) -> Result<String> {
+    let mut entry = String::new();
+    let mut group = None;
+    let mut found = vec![];
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            group = Some(&trimmed[1..trimmed.len() - 1]);
+        } else if group == Some("Desktop Entry") {
+            if let Some((key, value)) = trimmed.split_once('=') {
+                let key = key.trim();
+                let value = value.trim();
+                if REQUIRED_KEYS.contains(&key) {
+                    if value.is_empty() {
+                        bail!("{key} is empty");
+                    }
+                    found.push(key);
+                }
+                if key == "Exec" {
+                    let program = value.split_whitespace().next().unwrap_or_default();
+                    if program.trim_matches('"').rsplit('/').next() != Some(name) {
+                        bail!("Exec must run the executable {name}, got {value:?}");
+                    }
+                } else if key == "Icon" {
+                    entry += "Icon=icon\n";
+                    continue;
+                }
+            }
+        } else if group.is_none() && !trimmed.is_empty() && !trimmed.starts_with('#') {
+            bail!("Expected a group header before {trimmed:?}");
+        }
+        entry += line;
+        entry += "\n";
+    }
+    if !contents
+        .lines()
+        .any(|line| line.trim() == "[Desktop Entry]")
+    {
+        bail!("Missing the [Desktop Entry] group");
+    }
+    if let Some(missing) = REQUIRED_KEYS.iter().find(|key| !found.contains(key)) {
+        bail!("Missing the {missing} key in [Desktop Entry]");
+    }
+    Ok(entry)
+}
+
+/// Check an `exec` template: it has to start with the executable and only use the field
+/// codes of the spec, with at most one of the file and URL codes
+pub(crate) fn check_exec(exec: &str) -> Result<()> {
+    if exec.split_whitespace().next() != Some(EXEC_BIN) {
+        bail!("exec must start with {EXEC_BIN}, the executable, got {exec:?}");
+    }
+    let mut chars = exec.chars();
+    let mut files = 0;
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        match chars.next() {
+            Some(code) if FIELD_CODES.contains(&code) => {
+                if "fFuU".contains(code) {
+                    files += 1;
+                }
+            }
+            Some(code) => bail!("exec uses the field code %{code}, which is deprecated or not defined by the desktop entry spec"),
+            None => bail!("exec ends with a lone %, write %% for a literal one"),
+        }
+    }
+    if files > 1 {
+        bail!("exec may only use one of the field codes %f, %F, %u and %U");
+    }
+    Ok(())
+}
+
+/// Check that the entry of a terminal app is still shown by launchers: `Terminal` only
+/// applies to applications, and some launchers hide `ConsoleOnly` entries
+pub(crate) fn check_terminal(config: &AppImageConfig) -> Result<()> {
+    if !config.terminal {
+        return Ok(());
+    }
+    if let Some(desktop_type) = config
+        .desktop_type
+        .as_deref()
+        .filter(|desktop_type| *desktop_type != "Application")
+    {
+        bail!("terminal only applies to desktop_type Application, a {desktop_type} entry cannot start the app in a terminal");
+    }
+    if config.categories.iter().any(|c| c == "ConsoleOnly") {
+        diag::warning(
+            "Some launchers hide ConsoleOnly entries from their menus, leave it out of categories for the app to show up there",
+        );
+    }
+    Ok(())
+}
+
+/// Loosely check for a `lang`, `lang_COUNTRY` or `lang_COUNTRY@MODIFIER` locale as used
+/// for localized keys
+pub(crate) fn is_locale(locale: &str) -> bool {
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale, None),
+    };
+    let (lang, country) = match locale.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (locale, None),
+    };
+    (2..=3).contains(&lang.len())
+        && lang.chars().all(|c| c.is_ascii_lowercase())
+        && country.is_none_or(|c| c.len() == 2 && c.chars().all(|c| c.is_ascii_uppercase()))
+        && modifier.is_none_or(|m| !m.is_empty() && m.chars().all(|c| c.is_ascii_alphanumeric()))
+}
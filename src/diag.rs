@@ -0,0 +1,93 @@
+//! Colored diagnostics, plain when `NO_COLOR` is set or the output is not a terminal
+
+use crate::Failure;
+use owo_colors::{OwoColorize, Stream};
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+static DENY_WARNINGS: AtomicBool = AtomicBool::new(false);
+static DENY_IMMEDIATELY: AtomicBool = AtomicBool::new(false);
+static DENIED_WARNINGS: AtomicUsize = AtomicUsize::new(0);
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Plain progress and status messages
+pub fn info(message: impl Display) {
+    if to_stderr() {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+pub fn note(message: impl Display) {
+    let stream = stream();
+    info(format_args!(
+        "{} {message}",
+        "Note:".if_supports_color(stream, |t| t.cyan())
+    ));
+}
+
+pub fn success(message: impl Display) {
+    let stream = stream();
+    info(message.if_supports_color(stream, |t| t.green()));
+}
+
+pub fn warning(message: impl Display) {
+    eprintln!(
+        "{} {message}",
+        "Warning:".if_supports_color(Stream::Stderr, |t| t.yellow())
+    );
+    if DENY_WARNINGS.load(Ordering::Relaxed) {
+        DENIED_WARNINGS.fetch_add(1, Ordering::Relaxed);
+        if DENY_IMMEDIATELY.load(Ordering::Relaxed) {
+            error("Stopping at the first warning since warnings are denied");
+            std::process::exit(Failure::Warnings.exit_code());
+        }
+    }
+}
+
+pub fn error(message: impl Display) {
+    eprintln!(
+        "{} {message}",
+        "Error:".if_supports_color(Stream::Stderr, |t| t.red())
+    );
+}
+
+/// Count the warnings printed from now on so the build can fail because of them, or exit
+/// right away at the first one when `immediately` is set
+pub fn deny_warnings(immediately: bool) {
+    DENY_WARNINGS.store(true, Ordering::Relaxed);
+    DENY_IMMEDIATELY.store(immediately, Ordering::Relaxed);
+}
+
+/// Number of warnings printed since [`deny_warnings`] was called
+pub fn denied_warnings() -> usize {
+    DENIED_WARNINGS.load(Ordering::Relaxed)
+}
+
+/// Print the messages meant for people to stderr from now on, keeping stdout for the JSON
+/// messages of `--message-format=json`
+pub fn messages_to_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Whether the messages meant for people are printed to stderr
+pub(crate) fn to_stderr() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Stream the messages meant for people are printed to
+fn stream() -> Stream {
+    if to_stderr() {
+        Stream::Stderr
+    } else {
+        Stream::Stdout
+    }
+}
+
+/// Never color the output, e.g. when it is read by other programs
+pub fn disable_colors() {
+    owo_colors::set_override(false);
+}
@@ -0,0 +1,264 @@
+use crate::{AppImageConfig, MissingLibs};
+use cargo_toml::Value;
+use std::{collections::BTreeMap, path::Path};
+
+impl AppImageConfig {
+    /// Render the effective settings as TOML, using the keys of
+    /// `[package.metadata.appimage]` where there is one
+    pub fn to_toml(&self) -> String {
+        let entries = vec![
+            ("package_path", Some(path(&self.package_path))),
+            // `profile` holds the profile.<name> tables
+            ("cargo_profile", self.profile.clone().map(Value::String)),
+            ("target", self.target.clone().map(Value::String)),
+            ("targets", Some(strings(&self.targets))),
+            (
+                "arch_override",
+                self.arch_override.clone().map(Value::String),
+            ),
+            ("prefix_package", Some(Value::Boolean(self.prefix_package))),
+            ("cargo_args", Some(strings(&self.cargo_args))),
+            ("assets", Some(strings(&self.assets))),
+            (
+                "features",
+                Some(Value::Table(
+                    self.feature_assets
+                        .iter()
+                        .map(|(feature, assets)| {
+                            let mut settings = toml::Table::new();
+                            settings.insert("assets".to_string(), strings(assets));
+                            (feature.clone(), Value::Table(settings))
+                        })
+                        .collect(),
+                )),
+            ),
+            (
+                "assets_exclude",
+                Some(Value::Array(
+                    self.assets_exclude
+                        .iter()
+                        .map(|p| Value::String(p.as_str().to_string()))
+                        .collect(),
+                )),
+            ),
+            ("out_dir_assets", Some(strings(&self.out_dir_assets))),
+            (
+                "out_dir_assets_dir",
+                self.out_dir_assets_dir.clone().map(Value::String),
+            ),
+            (
+                "use_package_include",
+                Some(Value::Boolean(self.use_package_include)),
+            ),
+            (
+                "package_include_dir",
+                self.package_include_dir.clone().map(Value::String),
+            ),
+            ("splash", self.splash.as_deref().map(path)),
+            ("splash_dest", self.splash_dest.clone().map(Value::String)),
+            ("strip", Some(Value::Boolean(self.strip))),
+            ("upx", Some(Value::Boolean(self.upx))),
+            ("upx_args", Some(strings(&self.upx_args))),
+            ("auto_link", Some(Value::Boolean(self.auto_link))),
+            (
+                "auto_link_exclude_list",
+                Some(Value::Array(
+                    self.auto_link_exclude_list
+                        .iter()
+                        .map(|p| Value::String(p.as_str().to_string()))
+                        .collect(),
+                )),
+            ),
+            (
+                "lib_search_paths",
+                Some(Value::Array(
+                    self.lib_search_paths.iter().map(|p| path(p)).collect(),
+                )),
+            ),
+            (
+                "use_excludelist",
+                Some(Value::Boolean(self.use_excludelist)),
+            ),
+            (
+                "bundle_cxx_runtime",
+                Some(Value::Boolean(self.bundle_cxx_runtime)),
+            ),
+            ("lib_dir", self.lib_dir.clone().map(Value::String)),
+            (
+                "bundle_manifest",
+                Some(Value::Boolean(self.bundle_manifest)),
+            ),
+            (
+                "embed_build_info",
+                Some(Value::Boolean(self.embed_build_info)),
+            ),
+            (
+                "missing_libs",
+                Some(Value::String(
+                    match self.missing_libs {
+                        MissingLibs::Error => "error",
+                        MissingLibs::Warn => "warn",
+                    }
+                    .to_string(),
+                )),
+            ),
+            ("combine_bins", Some(Value::Boolean(self.combine_bins))),
+            ("primary_bin", self.primary_bin.clone().map(Value::String)),
+            ("exclude_bins", Some(strings(&self.exclude_bins))),
+            ("bins", self.include_bins.as_deref().map(strings)),
+            ("icon", self.icon.as_deref().map(path)),
+            ("default_icon", Some(Value::Boolean(!self.empty_icon))),
+            ("product_name", self.product_name.clone().map(Value::String)),
+            ("bin_name", self.bin_name.clone().map(Value::String)),
+            ("exec", self.exec.clone().map(Value::String)),
+            (
+                "exec_use_apprun",
+                Some(Value::Boolean(self.exec_use_apprun)),
+            ),
+            ("error_dialog", Some(Value::Boolean(self.error_dialog))),
+            (
+                "appimage_file_name",
+                self.appimage_file_name.clone().map(Value::String),
+            ),
+            ("name", self.desktop_name.clone().map(Value::String)),
+            ("comment", self.comment.clone().map(Value::String)),
+            ("name_localized", Some(string_table(&self.name_localized))),
+            (
+                "comment_localized",
+                Some(string_table(&self.comment_localized)),
+            ),
+            ("categories", Some(strings(&self.categories))),
+            ("keywords", Some(strings(&self.keywords))),
+            (
+                "keywords_localized",
+                Some(Value::Table(
+                    self.keywords_localized
+                        .iter()
+                        .map(|(locale, keywords)| (locale.clone(), strings(keywords)))
+                        .collect(),
+                )),
+            ),
+            ("gtk", Some(Value::Boolean(self.gtk))),
+            ("gpu_env", Some(Value::Boolean(self.gpu_env))),
+            ("env", Some(string_table(&self.env))),
+            ("terminal", Some(Value::Boolean(self.terminal))),
+            ("no_display", Some(Value::Boolean(self.no_display))),
+            ("integrate", self.integrate.map(Value::Boolean)),
+            (
+                "appimage_name",
+                self.appimage_name.clone().map(Value::String),
+            ),
+            (
+                "appimage_version",
+                self.appimage_version.clone().map(Value::String),
+            ),
+            ("desktop_type", self.desktop_type.clone().map(Value::String)),
+            ("desktop_file", self.desktop_file.as_deref().map(path)),
+            (
+                "desktop_spec_version",
+                self.desktop_spec_version.clone().map(Value::String),
+            ),
+            ("post_build", Some(strings(&self.post_build))),
+            ("version_env", self.version_env.clone().map(Value::String)),
+            ("args", Some(strings(&self.args))),
+            ("mksquashfs_args", Some(strings(&self.mksquashfs_args))),
+            ("squashfs_only", Some(Value::Boolean(self.squashfs_only))),
+            (
+                "fetch_appimagetool",
+                Some(Value::Boolean(self.fetch_appimagetool)),
+            ),
+            (
+                "appimagetool_version",
+                self.appimagetool_version.clone().map(Value::String),
+            ),
+            (
+                "appimagetool_sha256",
+                self.appimagetool_sha256.clone().map(Value::String),
+            ),
+            (
+                "tool_timeout",
+                self.tool_timeout
+                    .map(|t| Value::Integer(t.as_secs() as i64)),
+            ),
+            (
+                "tool_retries",
+                Some(Value::Integer(self.tool_retries.into())),
+            ),
+            ("reproducible", Some(Value::Boolean(self.reproducible))),
+            (
+                "normalize_permissions",
+                Some(Value::Boolean(self.normalize_permissions)),
+            ),
+            (
+                "source_date_epoch",
+                self.source_date_epoch.map(|e| Value::Integer(e as i64)),
+            ),
+            ("clean_appdir", Some(Value::Boolean(self.clean_appdir))),
+            ("checksum", Some(Value::Boolean(self.checksum))),
+            ("emit_tarball", Some(Value::Boolean(self.emit_tarball))),
+            ("emit_icon", Some(Value::Boolean(self.emit_icon))),
+            ("force", Some(Value::Boolean(self.force))),
+            ("binary", self.binary.as_deref().map(path)),
+            ("staging_dir", self.staging_dir.as_deref().map(path)),
+            ("quiet", Some(Value::Boolean(self.quiet))),
+            (
+                "profile",
+                Some(Value::Table(
+                    self.profiles
+                        .iter()
+                        .map(|(name, profile)| (name.clone(), profile.clone()))
+                        .collect(),
+                )),
+            ),
+            (
+                "bin",
+                Some(Value::Table(
+                    self.bins
+                        .iter()
+                        .map(|(name, bin)| (name.clone(), bin.clone()))
+                        .collect(),
+                )),
+            ),
+        ];
+
+        entries
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value?)))
+            .collect::<toml::Table>()
+            .to_string()
+    }
+}
+
+fn path(path: &Path) -> Value {
+    Value::String(path.display().to_string())
+}
+
+fn strings(v: &[String]) -> Value {
+    Value::Array(v.iter().cloned().map(Value::String).collect())
+}
+
+fn string_table(map: &BTreeMap<String, String>) -> Value {
+    Value::Table(
+        map.iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_tables_round_trip() {
+        let dist: Value = toml::from_str("strip = true\nupx_args = [\"--best\"]").unwrap();
+        let config = AppImageConfig {
+            profile: Some("dist".to_string()),
+            profiles: std::iter::once(("dist".to_string(), dist.clone())).collect(),
+            ..Default::default()
+        };
+        let dumped: toml::Table = config.to_toml().parse().unwrap();
+        assert_eq!(dumped["cargo_profile"].as_str(), Some("dist"));
+        assert_eq!(dumped["profile"]["dist"], dist);
+    }
+}
@@ -0,0 +1,85 @@
+//! `${VAR}` substitution in metadata values
+
+use anyhow::{bail, Context, Result};
+use cargo_toml::Value;
+
+/// Metadata keys whose strings are expanded, others like asset paths, `env` or
+/// `post_build` are taken literally
+pub(crate) const KEYS: &[&str] = &[
+    "appimage_name",
+    "appimage_version",
+    "product_name",
+    "name",
+    "comment",
+    "args",
+    "appimagetool_args",
+];
+
+/// Expand the substitutions in the `KEYS` of the metadata table `t`, including the ones
+/// of its `bin.<name>` and `profile.<name>` tables
+pub(crate) fn expand_table(t: &Value) -> Result<Value> {
+    let mut t = t.clone();
+    let Value::Table(table) = &mut t else {
+        return Ok(t);
+    };
+    for (key, value) in table.iter_mut() {
+        match value {
+            Value::String(s) if KEYS.contains(&key.as_str()) => *s = expand(key, s)?,
+            Value::Array(v) if KEYS.contains(&key.as_str()) => {
+                for item in v.iter_mut() {
+                    if let Value::String(s) = item {
+                        *s = expand(key, s)?;
+                    }
+                }
+            }
+            Value::Table(tables) if key == "bin" || key == "profile" => {
+                for (name, table) in tables.iter_mut() {
+                    *table = expand_table(table).with_context(|| format!("In {key}.{name}"))?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(t)
+}
+
+/// Replace `${VAR}` with the value of the env variable `VAR` and `${VAR:-default}` with
+/// `default` when `VAR` is unset or empty, `$$` is a literal `$`
+fn expand(key: &str, s: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(after) = rest.strip_prefix('{') else {
+            expanded.push('$');
+            continue;
+        };
+        let end = after
+            .find('}')
+            .with_context(|| format!("{key} has an unterminated ${{ in {s:?}"))?;
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("{key} references the invalid env variable name {name:?}");
+        }
+        match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => expanded.push_str(default),
+            (Ok(value), _) => expanded.push_str(&value),
+            (Err(_), Some(default)) => expanded.push_str(default),
+            (Err(_), None) => bail!(
+                "{key} references ${{{name}}}, which is not set, use ${{{name}:-default}} for a fallback"
+            ),
+        }
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
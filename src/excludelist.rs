@@ -0,0 +1,22 @@
+//! The AppImage excludelist of libraries that are expected on every system
+
+const EXCLUDELIST: &str = include_str!("excludelist");
+
+/// Patterns for the libraries of the bundled excludelist
+pub(crate) fn patterns() -> Vec<glob::Pattern> {
+    EXCLUDELIST
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| glob::Pattern::new(line).expect("excludelist entries are valid patterns"))
+        .collect()
+}
+
+/// Libraries of the GCC C++ runtime that `bundle_cxx_runtime` bundles despite the exclude
+/// lists
+const CXX_RUNTIME: [&str; 2] = ["libstdc++.so.6", "libgcc_s.so.1"];
+
+/// Whether the library file `file_name` is part of the GCC C++ runtime
+pub(crate) fn is_cxx_runtime(file_name: &str) -> bool {
+    CXX_RUNTIME.contains(&file_name)
+}
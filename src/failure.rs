@@ -0,0 +1,59 @@
+//! Stages a build can fail in, each exiting with its own code so scripts can tell them
+//! apart
+
+use std::fmt;
+
+/// Stage of the build that failed. It is attached as context to the error, `main` turns
+/// it into the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The command line, the manifest or its `[package.metadata.appimage]` is invalid
+    Config,
+    /// `cargo metadata` failed
+    Metadata,
+    /// `cargo build` failed
+    Build,
+    /// The libraries or assets could not be staged in the AppDir
+    Staging,
+    /// appimagetool, or mksquashfs for bare squashfs images, or the runner copied to
+    /// `AppRun` is not installed
+    MissingTool,
+    /// appimagetool or mksquashfs failed, or appimagetool timed out
+    Appimagetool,
+    /// Warnings were printed while they are denied
+    Warnings,
+}
+
+impl Failure {
+    /// Exit code reported for this failure, these stay the same across releases
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Failure::Config => 2,
+            Failure::Metadata => 3,
+            Failure::Build => 4,
+            Failure::Staging => 5,
+            Failure::MissingTool => 6,
+            Failure::Appimagetool => 7,
+            Failure::Warnings => 8,
+        }
+    }
+
+    /// Exit code for `error`, 1 when it did not happen in one of the known stages
+    pub fn exit_code_of(error: &anyhow::Error) -> i32 {
+        error.downcast_ref::<Failure>().map_or(1, |f| f.exit_code())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Failure::Config => "Invalid configuration",
+            Failure::Metadata => "Could not read the cargo metadata",
+            Failure::Build => "Could not build the binaries",
+            Failure::Staging => "Could not stage the AppDir",
+            Failure::MissingTool => "A packaging tool is not installed",
+            Failure::Appimagetool => "Could not create the image",
+            Failure::Warnings => "Warnings are denied",
+        })
+    }
+}
@@ -0,0 +1,66 @@
+//! Features of the package enabled by the arguments passed on to `cargo build`
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Features of `package`, declared in `declared`, that `cargo build` enables when passed
+/// `cargo_args`, including the ones they enable in turn
+pub(crate) fn enabled(
+    package: &str,
+    declared: &BTreeMap<String, Vec<String>>,
+    cargo_args: &[String],
+) -> BTreeSet<String> {
+    let mut requested = vec![];
+    let mut default_features = true;
+    let mut args = cargo_args.iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "--" => break,
+            "--all-features" => {
+                requested.extend(declared.keys().cloned());
+                continue;
+            }
+            "--no-default-features" => {
+                default_features = false;
+                continue;
+            }
+            "--features" | "-F" => args.next().map(String::as_str),
+            arg => arg
+                .strip_prefix("--features=")
+                .or_else(|| arg.strip_prefix("-F")),
+        };
+        // Features are separated by commas or spaces, those of other packages are prefixed
+        // with their name
+        for feature in value
+            .unwrap_or_default()
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|feature| !feature.is_empty())
+        {
+            match feature.split_once('/') {
+                Some((name, feature)) if name == package => requested.push(feature.to_string()),
+                Some(_) => {}
+                None => requested.push(feature.to_string()),
+            }
+        }
+    }
+    if default_features && declared.contains_key("default") {
+        requested.push("default".to_string());
+    }
+
+    let mut enabled = BTreeSet::new();
+    while let Some(feature) = requested.pop() {
+        if !enabled.insert(feature.clone()) {
+            continue;
+        }
+        // Optional dependencies and the features of dependencies are not features of the
+        // package itself
+        requested.extend(
+            declared
+                .get(&feature)
+                .into_iter()
+                .flatten()
+                .filter(|implied| !implied.starts_with("dep:") && !implied.contains('/'))
+                .cloned(),
+        );
+    }
+    enabled
+}
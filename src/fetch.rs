@@ -0,0 +1,173 @@
+//! Download of appimagetool into the user cache for machines that do not have it
+
+use crate::{checksum, diag};
+use anyhow::{bail, Context, Result};
+use std::{
+    io::ErrorKind,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Where the AppImages of appimagetool are published, by release
+const RELEASES_URL: &str = "https://github.com/AppImage/appimagetool/releases/download";
+
+/// Path of the cached appimagetool for this machine, downloading it first when it is not
+/// cached yet. `version` picks the release, the latest continuous build when `None`. The
+/// download is checked against `sha256` when given, the cached copy against the checksum
+/// recorded when it was downloaded.
+pub(crate) fn appimagetool(
+    version: Option<&str>,
+    sha256: Option<&str>,
+    quiet: bool,
+) -> Result<PathBuf> {
+    let arch = host_arch()?;
+    let file_name = format!("appimagetool-{arch}.AppImage");
+    let cached_name = match version {
+        Some(version) => format!("appimagetool-{version}-{arch}.AppImage"),
+        None => file_name.clone(),
+    };
+    let dir = cache_dir()?;
+    let tool = dir.join(&cached_name);
+    let recorded = dir.join(format!("{cached_name}.sha256"));
+
+    if cached(&tool, &recorded, sha256)? {
+        return Ok(tool);
+    }
+
+    std::fs::create_dir_all(&dir).with_context(|| format!("Could not create {}", dir.display()))?;
+    let url = format!(
+        "{RELEASES_URL}/{}/{file_name}",
+        version.unwrap_or("continuous")
+    );
+    if !quiet {
+        diag::info(format_args!("Downloading {url}"));
+    }
+    let partial = dir.join(format!("{cached_name}.part"));
+    download(&url, &partial)?;
+    let actual = checksum::sha256(&partial)?;
+    match sha256 {
+        Some(expected) if expected != actual => {
+            let _ = std::fs::remove_file(&partial);
+            bail!("{url} has the checksum {actual} instead of the expected appimagetool_sha256 {expected}");
+        }
+        Some(_) => {}
+        // Only the cached copy is checked against the recorded checksum from now on
+        None => diag::warning(format_args!(
+            "appimagetool_sha256 is not set, so {url} was not verified. Its checksum is {actual}, check it and set appimagetool_sha256 to verify downloads"
+        )),
+    }
+    std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Could not make {} executable", partial.display()))?;
+    std::fs::write(&recorded, format!("{actual}\n"))
+        .with_context(|| format!("Could not write {}", recorded.display()))?;
+    std::fs::rename(&partial, &tool)
+        .with_context(|| format!("Could not move {} into place", partial.display()))?;
+    Ok(tool)
+}
+
+/// Whether `tool` is cached and has the checksum `sha256`, or the one recorded in `recorded`
+/// when it was downloaded. A copy without a recorded checksum is not trusted.
+fn cached(tool: &Path, recorded: &Path, sha256: Option<&str>) -> Result<bool> {
+    if !tool.is_file() {
+        return Ok(false);
+    }
+    let expected = match sha256 {
+        Some(sha256) => sha256.to_string(),
+        None => match std::fs::read_to_string(recorded) {
+            Ok(expected) => expected.trim().to_string(),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                diag::warning(format_args!(
+                    "The cached {} has no recorded checksum to verify it, downloading it again",
+                    tool.display()
+                ));
+                return Ok(false);
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Could not read {}", recorded.display()))
+            }
+        },
+    };
+    let actual = checksum::sha256(tool)?;
+    if actual != expected {
+        diag::warning(format_args!(
+            "The cached {} has the checksum {actual} instead of {expected}, downloading it again",
+            tool.display()
+        ));
+    }
+    Ok(actual == expected)
+}
+
+/// `$XDG_CACHE_HOME/cargo-appimage`, `~/.cache/cargo-appimage` when it is unset
+fn cache_dir() -> Result<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").context("Could not get home path")?)
+            .join(".cache"),
+    };
+    Ok(cache.join("cargo-appimage"))
+}
+
+/// Architecture appimagetool is published for that runs on this machine
+fn host_arch() -> Result<&'static str> {
+    Ok(match std::env::consts::ARCH {
+        "x86_64" => "x86_64",
+        "aarch64" => "aarch64",
+        "x86" => "i686",
+        "arm" => "armhf",
+        arch => bail!("appimagetool is not published for {arch}, install it yourself"),
+    })
+}
+
+/// Download `url` to `dest` with curl, or wget when curl is not installed
+fn download(url: &str, dest: &Path) -> Result<()> {
+    let status = match Command::new("curl")
+        .args([
+            "--fail",
+            "--location",
+            "--silent",
+            "--show-error",
+            "--output",
+        ])
+        .arg(dest)
+        .arg(url)
+        .status()
+    {
+        Err(e) if e.kind() == ErrorKind::NotFound => Command::new("wget")
+            .args(["--quiet", "--output-document"])
+            .arg(dest)
+            .arg(url)
+            .status()
+            .context("Downloading appimagetool needs curl or wget")?,
+        status => status.context("Could not run curl")?,
+    };
+    if !status.success() {
+        let _ = std::fs::remove_file(dest);
+        bail!("Downloading {url} failed with {status}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unrecorded_cache_is_downloaded_again() {
+        let dir =
+            std::env::temp_dir().join(format!("cargo-appimage-fetch-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("appimagetool-x86_64.AppImage");
+        let recorded = dir.join("appimagetool-x86_64.AppImage.sha256");
+        std::fs::write(&tool, "appimagetool").unwrap();
+        let actual = checksum::sha256(&tool).unwrap();
+
+        assert!(!cached(&tool, &recorded, None).unwrap());
+        assert!(cached(&tool, &recorded, Some(&actual)).unwrap());
+        std::fs::write(&recorded, format!("{actual}\n")).unwrap();
+        assert!(cached(&tool, &recorded, None).unwrap());
+        std::fs::write(&recorded, "0".repeat(64)).unwrap();
+        assert!(!cached(&tool, &recorded, None).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,26 @@
+//! Availability of FUSE, which appimagetool and the AppImages it creates mount themselves
+//! with
+
+use crate::{diag, in_path};
+use std::path::Path;
+
+/// Warn when FUSE cannot be used on this machine, unless AppImages are told to extract
+/// themselves instead. Packaging goes on either way, the AppImages may be run elsewhere.
+pub(crate) fn warn_if_missing() {
+    if std::env::var_os("APPIMAGE_EXTRACT_AND_RUN").is_some() {
+        return;
+    }
+    let mut missing = vec![];
+    if !Path::new("/dev/fuse").exists() {
+        missing.push("/dev/fuse");
+    }
+    if !in_path("fusermount") && !in_path("fusermount3") {
+        missing.push("fusermount");
+    }
+    if !missing.is_empty() {
+        diag::warning(format_args!(
+            "FUSE is not available, {} not found. appimagetool and the AppImages it creates cannot mount themselves here, set APPIMAGE_EXTRACT_AND_RUN=1 or run them with --appimage-extract-and-run",
+            missing.join(" and ")
+        ));
+    }
+}
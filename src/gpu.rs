@@ -0,0 +1,81 @@
+//! Env variables pointing the graphics stack at GPU drivers staged in the AppDir
+
+use crate::{diag, gtk::set_default};
+use anyhow::Result;
+use std::{collections::BTreeMap, path::Path};
+
+/// Where Mesa's DRI and VA-API drivers are staged, relative to the AppDir
+const DRI_DIRS: [&str; 3] = ["usr/lib/dri", "usr/lib64/dri", "usr/lib/*/dri"];
+/// GLVND vendor files of the EGL implementations
+const EGL_VENDOR_DIR: &str = "usr/share/glvnd/egl_vendor.d";
+/// Vulkan ICD manifests of the drivers
+const VULKAN_ICD_DIR: &str = "usr/share/vulkan/icd.d";
+
+/// Add the env variables pointing Mesa, GLVND and the Vulkan loader at the drivers found
+/// in `appdir` to `env`, keeping any the user set, and warn about the ones not found
+pub(crate) fn configure(appdir: &Path, env: &mut BTreeMap<String, String>) -> Result<()> {
+    let mut missing = vec![];
+
+    let dri = DRI_DIRS
+        .iter()
+        .filter_map(|pattern| glob::glob(&in_appdir(appdir, pattern)).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .find(|dir| dir.is_dir());
+    match dri.as_deref().and_then(|dir| dir.strip_prefix(appdir).ok()) {
+        Some(dri) => {
+            let dri = format!("$APPDIR/{}", dri.display());
+            set_default(env, "LIBGL_DRIVERS_PATH", dri.clone());
+            set_default(env, "LIBVA_DRIVERS_PATH", dri);
+        }
+        None => missing.push("usr/lib/dri"),
+    }
+
+    // Mesa's GLX has to be picked over the vendor of the host, which GLVND would load
+    if glob::glob(&in_appdir(appdir, "**/libGLX_mesa.so.0"))?.any(|lib| lib.is_ok()) {
+        set_default(env, "__GLX_VENDOR_LIBRARY_NAME", "mesa".to_string());
+    }
+
+    if appdir.join(EGL_VENDOR_DIR).is_dir() {
+        set_default(
+            env,
+            "__EGL_VENDOR_LIBRARY_DIRS",
+            format!("$APPDIR/{EGL_VENDOR_DIR}"),
+        );
+    } else {
+        missing.push(EGL_VENDOR_DIR);
+    }
+
+    // The Vulkan loader takes the manifests themselves, older ones only know
+    // VK_ICD_FILENAMES
+    let mut icds: Vec<String> = std::fs::read_dir(appdir.join(VULKAN_ICD_DIR))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".json"))
+        .map(|name| format!("$APPDIR/{VULKAN_ICD_DIR}/{name}"))
+        .collect();
+    icds.sort();
+    if icds.is_empty() {
+        missing.push(VULKAN_ICD_DIR);
+    } else {
+        set_default(env, "VK_DRIVER_FILES", icds.join(":"));
+        set_default(env, "VK_ICD_FILENAMES", icds.join(":"));
+    }
+
+    if !missing.is_empty() {
+        diag::warning(format_args!(
+            "gpu_env: no drivers found in {} of the AppDir, stage the drivers with assets for their env variables to be set",
+            missing.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Glob of `pattern` relative to `appdir`, whose own path is matched literally
+fn in_appdir(appdir: &Path, pattern: &str) -> String {
+    format!(
+        "{}/{pattern}",
+        glob::Pattern::escape(&appdir.to_string_lossy())
+    )
+}
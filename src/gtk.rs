@@ -0,0 +1,108 @@
+//! Staging of the host files GTK apps need at runtime besides their libraries
+
+use crate::diag;
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const SCHEMAS_DIR: &str = "usr/share/glib-2.0/schemas";
+const PIXBUF_DIR: &str = "usr/lib/gdk-pixbuf-2.0";
+/// Where distributions put the GdkPixbuf loaders when pkg-config does not know
+const PIXBUF_LOADER_DIRS: [&str; 3] = [
+    "/usr/lib/*/gdk-pixbuf-2.0/2.10.0/loaders",
+    "/usr/lib64/gdk-pixbuf-2.0/2.10.0/loaders",
+    "/usr/lib/gdk-pixbuf-2.0/2.10.0/loaders",
+];
+
+/// Copy the compiled GSettings schemas and the GdkPixbuf loaders of the host into
+/// `appdir` and add the env variables pointing GTK at them to `env`, keeping any the
+/// user set
+pub(crate) fn stage(appdir: &Path, env: &mut BTreeMap<String, String>) -> Result<()> {
+    let schemas = Path::new("/usr/share/glib-2.0/schemas/gschemas.compiled");
+    if schemas.is_file() {
+        let dest = appdir.join(SCHEMAS_DIR);
+        fs_extra::dir::create_all(&dest, false)?;
+        std::fs::copy(schemas, dest.join("gschemas.compiled"))
+            .with_context(|| format!("Error copying {}", schemas.display()))?;
+        set_default(
+            env,
+            "GSETTINGS_SCHEMA_DIR",
+            format!("$APPDIR/{SCHEMAS_DIR}"),
+        );
+    } else {
+        diag::warning(format_args!(
+            "gtk: {} not found, install the GSettings schemas your app uses",
+            schemas.display()
+        ));
+    }
+
+    let Some(loaders) = pixbuf_loader_dir() else {
+        diag::warning("gtk: GdkPixbuf loaders not found, install gdk-pixbuf to bundle them");
+        return Ok(());
+    };
+    let dest = appdir.join(PIXBUF_DIR).join("loaders");
+    fs_extra::dir::create_all(&dest, false)?;
+    for loader in std::fs::read_dir(&loaders)
+        .with_context(|| format!("Could not read {}", loaders.display()))?
+    {
+        let loader = loader?.path();
+        if let Some(file_name) = loader.file_name() {
+            std::fs::copy(&loader, dest.join(file_name))
+                .with_context(|| format!("Error copying {}", loader.display()))?;
+        }
+    }
+
+    // The cache lists the loaders by absolute path, relative ones are looked up in
+    // GDK_PIXBUF_MODULEDIR instead
+    let cache = loaders.with_file_name("loaders.cache");
+    match std::fs::read_to_string(&cache) {
+        Ok(contents) => {
+            let prefix = format!("{}/", loaders.display());
+            std::fs::write(
+                appdir.join(PIXBUF_DIR).join("loaders.cache"),
+                contents.replace(&prefix, ""),
+            )
+            .context("Error writing the GdkPixbuf loaders cache")?;
+            set_default(
+                env,
+                "GDK_PIXBUF_MODULE_FILE",
+                format!("$APPDIR/{PIXBUF_DIR}/loaders.cache"),
+            );
+        }
+        Err(_) => diag::warning(format_args!(
+            "gtk: {} not found, run gdk-pixbuf-query-loaders --update-cache",
+            cache.display()
+        )),
+    }
+    set_default(
+        env,
+        "GDK_PIXBUF_MODULEDIR",
+        format!("$APPDIR/{PIXBUF_DIR}/loaders"),
+    );
+    Ok(())
+}
+
+fn pixbuf_loader_dir() -> Option<PathBuf> {
+    let from_pkg_config = Command::new("pkg-config")
+        .args(["--variable=gdk_pixbuf_moduledir", "gdk-pixbuf-2.0"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()));
+    from_pkg_config.filter(|dir| dir.is_dir()).or_else(|| {
+        PIXBUF_LOADER_DIRS
+            .iter()
+            .filter_map(|pattern| glob::glob(pattern).ok())
+            .flatten()
+            .filter_map(Result::ok)
+            .find(|dir| dir.is_dir())
+    })
+}
+
+/// Set the env variable `name` to `value` unless it is already set
+pub(crate) fn set_default(env: &mut BTreeMap<String, String>, name: &str, value: String) {
+    env.entry(name.to_string()).or_insert(value);
+}
@@ -0,0 +1,72 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+const PNG_SIGNATURE: [u8; 8] = *b"\x89PNG\r\n\x1a\n";
+
+/// Generic 256x256 app icon packaged when the package has no icon
+pub(crate) const DEFAULT_ICON: &[u8] = include_bytes!("default-icon.png");
+
+/// Sizes of the `hicolor` icon theme directories icons are commonly looked up in
+pub(crate) const HICOLOR_SIZES: [u32; 7] = [16, 32, 48, 64, 128, 256, 512];
+
+/// Find the icon of the binary `name`: the configured icon, `<name>.png` or `icon.png`
+/// in the package root `root`, in that order. `None` when there is none.
+pub(crate) fn find(root: &Path, name: &str, configured: Option<&Path>) -> Result<Option<PathBuf>> {
+    if let Some(icon) = configured {
+        if !icon.is_file() {
+            bail!("Icon {} does not exist", icon.display());
+        }
+        return Ok(Some(icon.to_path_buf()));
+    }
+    Ok([root.join(format!("{name}.png")), root.join("icon.png")]
+        .iter()
+        .find(|icon| icon.is_file())
+        .cloned())
+}
+
+/// Check that `path` is a PNG file and return its width and height, `None` for the
+/// empty placeholder icon
+pub(crate) fn png_size(path: &Path) -> Result<Option<(u32, u32)>> {
+    let mut header = Vec::with_capacity(24);
+    File::open(path)
+        .and_then(|f| f.take(24).read_to_end(&mut header))
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    if header.is_empty() {
+        return Ok(None);
+    }
+
+    // The signature is followed by the IHDR chunk: length, type, width and height
+    if header.len() < 24 || header[..8] != PNG_SIGNATURE {
+        bail!(
+            "{} is not a PNG image, convert it to PNG or remove it to use the generic default icon, or an empty one with --no-default-icon",
+            path.display()
+        );
+    }
+    if &header[12..16] != b"IHDR" {
+        bail!(
+            "{} is a corrupt PNG image, it has no IHDR header",
+            path.display()
+        );
+    }
+    let width = u32::from_be_bytes(header[16..20].try_into()?);
+    let height = u32::from_be_bytes(header[20..24].try_into()?);
+    if width == 0 || height == 0 {
+        bail!(
+            "{} is a corrupt PNG image of size {width}x{height}",
+            path.display()
+        );
+    }
+    Ok(Some((width, height)))
+}
+
+/// Path inside the AppDir the icon of size `width`x`height` has in the `hicolor` theme,
+/// `None` when it is not one of the standard sizes
+pub(crate) fn hicolor_path(width: u32, height: u32) -> Option<String> {
+    (width == height && HICOLOR_SIZES.contains(&width))
+        .then(|| format!("usr/share/icons/hicolor/{width}x{height}/apps/icon.png"))
+}
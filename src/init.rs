@@ -0,0 +1,64 @@
+use crate::{diag, get_manifest_from_path};
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+const METADATA_TEMPLATE: &str = r#"
+[package.metadata.appimage]
+# Files and directories copied into the root of the AppImage
+# assets = ["assets"]
+# Embed the shared libraries the binary links to
+# auto_link = true
+# auto_link_exclude_list = ["libc.so*"]
+# Extra arguments passed on to appimagetool
+# args = ["--comp", "zstd"]
+# Categories of the desktop entry
+# categories = ["Utility"]
+# Icon of the app, icon.png next to Cargo.toml when unset
+# icon = "icon.png"
+"#;
+
+/// Add a commented `[package.metadata.appimage]` section to the manifest of the package at
+/// `package_path`, leaving manifests that already have one alone
+pub fn init_manifest<P: AsRef<Path>>(package_path: P) -> Result<()> {
+    let (path, manifest) = get_manifest_from_path(&package_path)?;
+    if manifest.package.is_none() {
+        bail!(
+            "{} has no [package], run init in the directory of a package",
+            path.display()
+        );
+    }
+    let mut contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+
+    let has_section = contents.lines().any(|line| {
+        let line = line.trim();
+        line.starts_with("[package.metadata.appimage]")
+            || line.starts_with("[package.metadata.appimage.")
+    });
+    if has_section {
+        diag::note(format_args!(
+            "{} already has a [package.metadata.appimage] section",
+            path.display()
+        ));
+    } else {
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents += METADATA_TEMPLATE;
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Could not write {}", path.display()))?;
+        diag::success(format_args!(
+            "Added [package.metadata.appimage] to {}",
+            path.display()
+        ));
+    }
+
+    let icon = path.with_file_name("icon.png");
+    if !icon.exists() {
+        diag::note(format_args!(
+            "Add an icon at {}, the generic default icon is used until then unless --no-default-icon is passed",
+            icon.display()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,2366 @@
+use anyhow::{anyhow, bail, Context, Result};
+use cargo_toml::Value;
+use fs_extra::dir::CopyOptions;
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+mod appimagetool;
+mod arch;
+mod build_info;
+mod cargo_build;
+mod categories;
+mod check;
+mod checksum;
+mod desktop;
+pub mod diag;
+mod dump;
+mod envsubst;
+mod excludelist;
+mod failure;
+mod features;
+mod fetch;
+mod fuse;
+mod gpu;
+mod gtk;
+mod icon;
+mod init;
+mod permissions;
+mod plan;
+mod progress;
+mod reproducible;
+pub mod runner_config;
+mod squashfs;
+mod strip;
+mod tarball;
+mod upx;
+
+pub use check::check_config;
+pub use failure::Failure;
+pub use init::init_manifest;
+pub use plan::{plan, BinaryPlan};
+use progress::Progress;
+use runner_config::RunnerConfig;
+
+pub const CARGO_APPIMAGE_PACKAGE_PATH: &str = "CARGO_APPIMAGE_PACKAGE_PATH";
+pub const CARGO_APPIMAGE_PACKAGE: &str = "CARGO_APPIMAGE_PACKAGE";
+pub const CARGO_APPIMAGE_PROFILE: &str = "CARGO_APPIMAGE_PROFILE";
+pub const CARGO_APPIMAGE_VERSION: &str = "CARGO_APPIMAGE_VERSION";
+const CARGO_FNAME: &str = "Cargo.toml";
+const APPIMAGE_RUNNER: &str = "cargo-appimage-runner";
+/// Directory under the target dir the AppDirs are staged in
+const STAGING_DIR: &str = "cargo-appimage";
+/// Name of the desktop entry in the root of the AppDir
+const DESKTOP_FILE: &str = "cargo-appimage.desktop";
+/// mksquashfs options appimagetool sets itself
+const RESERVED_MKSQUASHFS_ARGS: [(&str, &str); 3] = [
+    ("-comp", "use appimagetool's --comp in args instead"),
+    (
+        "-offset",
+        "appimagetool places the filesystem after the runtime",
+    ),
+    ("-noappend", "appimagetool always creates a new filesystem"),
+];
+
+/// Everything needed to turn a package into one AppImage per binary
+#[derive(Debug, Clone, Default)]
+pub struct AppImageConfig {
+    /// Path to the package directory or to its manifest
+    pub package_path: PathBuf,
+    /// Cargo profile to build with, `release` when unset
+    pub profile: Option<String>,
+    /// Target triple to build for, the host when unset
+    pub target: Option<String>,
+    /// Target triples to build and package one after the other when `target` is unset
+    pub targets: Vec<String>,
+    /// Name the AppImages and AppDirs `<package>-<binary>`, e.g. to tell apart binaries of
+    /// the same name in different workspace members
+    pub prefix_package: bool,
+    /// Architecture passed to appimagetool in `ARCH` and used in file names instead of the
+    /// one derived from the target
+    pub arch_override: Option<String>,
+    /// Add the architecture to the AppImage file names, set when building several targets
+    pub name_with_arch: bool,
+    /// Extra arguments passed on to `cargo build`
+    pub cargo_args: Vec<String>,
+    /// Files and directories copied into the root of every AppDir
+    pub assets: Vec<String>,
+    /// Extra assets of features of the package, copied when the feature is enabled
+    pub feature_assets: BTreeMap<String, Vec<String>>,
+    /// Files matched by asset globs that are left out, relative to the package root
+    pub assets_exclude: Vec<glob::Pattern>,
+    /// Globs of files written to `OUT_DIR` by the build script that are copied into every
+    /// AppDir, keeping their path relative to `OUT_DIR`
+    pub out_dir_assets: Vec<String>,
+    /// Directory inside the AppDir `out_dir_assets` are copied to, the root when unset
+    pub out_dir_assets_dir: Option<String>,
+    /// Also copy the files matched by the `include` globs of `[package]`
+    pub use_package_include: bool,
+    /// AppDir relative directory the `include` files are copied to, the root of the
+    /// AppDir when unset
+    pub package_include_dir: Option<String>,
+    /// Splash image shown by the app while it loads
+    pub splash: Option<PathBuf>,
+    /// AppDir relative path the splash image is copied to,
+    /// `usr/share/<name>/splash.png` when unset
+    pub splash_dest: Option<String>,
+    /// Strip the symbols from the packaged binaries, unless the cargo profile already does
+    pub strip: bool,
+    /// Compress the packaged binaries with UPX
+    pub upx: bool,
+    /// Arguments passed to UPX, e.g. `--best`
+    pub upx_args: Vec<String>,
+    /// Embed the shared libraries the binaries link to
+    pub auto_link: bool,
+    /// Libraries matching any of these patterns are never embedded
+    pub auto_link_exclude_list: Vec<glob::Pattern>,
+    /// Directories searched for the libraries to embed before the ones of the system
+    pub lib_search_paths: Vec<PathBuf>,
+    /// Also exclude the libraries of the AppImage excludelist shipped with cargo-appimage
+    pub use_excludelist: bool,
+    /// Bundle libstdc++ and libgcc_s even when the exclude lists leave them out
+    pub bundle_cxx_runtime: bool,
+    /// AppDir relative directory the embedded libraries are copied to, by default they
+    /// keep the location they have on the build machine
+    pub lib_dir: Option<String>,
+    /// Record the SONAME and source path of every embedded library in
+    /// `usr/share/doc/<name>/bundled-libs.txt`
+    pub bundle_manifest: bool,
+    /// Write `usr/share/<name>/build-info.json` describing the build into every AppDir
+    pub embed_build_info: bool,
+    /// Put all packaged binaries in a single AppImage, named after and starting the
+    /// primary binary
+    pub combine_bins: bool,
+    /// Binary started by a combined AppImage, the `default-run` of the package when unset
+    pub primary_bin: Option<String>,
+    /// Names of binaries that are not packaged
+    pub exclude_bins: Vec<String>,
+    /// Names of the only binaries that are packaged, all of them when unset
+    pub include_bins: Option<Vec<String>>,
+    /// Icon of the app, `icon.png` in the package root when unset
+    pub icon: Option<PathBuf>,
+    /// Package an empty icon instead of the generic default icon when there is none, so
+    /// appimagetool falls back to its own
+    pub empty_icon: bool,
+    /// Name of the product, used for the AppImage file name and the desktop entry `Name`
+    /// instead of the name of the binary
+    pub product_name: Option<String>,
+    /// File name of the executable in `usr/bin` and the desktop entry `Exec`, the name of
+    /// the binary when unset
+    pub bin_name: Option<String>,
+    /// File name of the AppImage without extension, overriding the product name. Only
+    /// valid when a single binary is packaged.
+    pub appimage_file_name: Option<String>,
+    /// Template of the desktop entry `Exec`, `{bin}` is replaced with the executable
+    pub exec: Option<String>,
+    /// Start the app through `AppRun` from the desktop entry, so `{bin}` in `exec` is
+    /// replaced with `AppRun` instead of the executable
+    pub exec_use_apprun: bool,
+    /// Have the AppRun show the end of stderr in a dialog and log it when the app fails
+    pub error_dialog: bool,
+    /// Desktop entry `Name`, the product name when unset
+    pub desktop_name: Option<String>,
+    /// Desktop entry `Comment`
+    pub comment: Option<String>,
+    /// Translations of the desktop entry `Name` by locale, e.g. `de` or `pt_BR`
+    pub name_localized: BTreeMap<String, String>,
+    /// Translations of the desktop entry `Comment` by locale
+    pub comment_localized: BTreeMap<String, String>,
+    /// Desktop entry `Categories`, `Utility` when empty
+    pub categories: Vec<String>,
+    /// Desktop entry `Keywords` launchers search in
+    pub keywords: Vec<String>,
+    /// Translations of the desktop entry `Keywords` by locale
+    pub keywords_localized: BTreeMap<String, Vec<String>>,
+    /// Bundle the GSettings schemas and GdkPixbuf loaders GTK apps need
+    pub gtk: bool,
+    /// Point the graphics stack at the GPU drivers staged in the AppDir
+    pub gpu_env: bool,
+    /// Env variables set by the AppRun before starting the binary, `$APPDIR` in the
+    /// values is replaced with the location of the mounted AppImage
+    pub env: BTreeMap<String, String>,
+    /// `[package.metadata.appimage.bin.<name>]` tables overriding these settings for a
+    /// single binary
+    pub bins: BTreeMap<String, Value>,
+    /// `[package.metadata.appimage.profile.<name>]` tables overriding these settings when
+    /// building with the cargo profile `name`
+    pub profiles: BTreeMap<String, Value>,
+    /// Launch the app in a terminal, for command line tools
+    pub terminal: bool,
+    /// Hide the desktop entry from application menus, e.g. for background services
+    pub no_display: bool,
+    /// `Some(false)` keeps the AppImage runtime from offering to integrate the app into
+    /// the system, for portable AppImages
+    pub integrate: Option<bool>,
+    /// Desktop entry `X-AppImage-Name`, the product name when unset
+    pub appimage_name: Option<String>,
+    /// Desktop entry `X-AppImage-Version`, the package version when unset
+    pub appimage_version: Option<String>,
+    /// Desktop entry `Type`, `Application` when unset
+    pub desktop_type: Option<String>,
+    /// Existing desktop entry to package instead of generating one
+    pub desktop_file: Option<PathBuf>,
+    /// Desktop entry spec `Version` the entry claims to follow, not the version of the
+    /// app, `1.0` when unset
+    pub desktop_spec_version: Option<String>,
+    /// What to do when a library the binaries link to is not found on the build machine
+    pub missing_libs: MissingLibs,
+    /// Command run in the package root after each AppImage is created, with the
+    /// `CARGO_APPIMAGE_OUTPUT`, `CARGO_APPIMAGE_NAME`, `CARGO_APPIMAGE_VERSION` and,
+    /// unless the AppDir was removed, `CARGO_APPIMAGE_APPDIR` env variables set
+    pub post_build: Vec<String>,
+    /// Env variable that overrides the version of the package when set,
+    /// `CARGO_APPIMAGE_VERSION` when unset
+    pub version_env: Option<String>,
+    /// Extra arguments passed on to `appimagetool`
+    pub args: Vec<String>,
+    /// Extra options passed on to mksquashfs through `--mksquashfs-opt`
+    pub mksquashfs_args: Vec<String>,
+    /// Create a bare squashfs image of the AppDir with mksquashfs instead of an AppImage
+    pub squashfs_only: bool,
+    /// Download appimagetool into the user cache when it is not installed
+    pub fetch_appimagetool: bool,
+    /// Version of appimagetool to download with `fetch_appimagetool` and to expect from
+    /// the installed one
+    pub appimagetool_version: Option<String>,
+    /// Expected SHA-256 of the appimagetool downloaded with `fetch_appimagetool`
+    pub appimagetool_sha256: Option<String>,
+    /// Kill appimagetool and fail if it runs for longer than this
+    pub tool_timeout: Option<Duration>,
+    /// Run appimagetool again this many times when it fails
+    pub tool_retries: u32,
+    /// Normalize timestamps so that the same sources produce the same AppImage
+    pub reproducible: bool,
+    /// Set the permissions of the AppDir contents to 0755 for directories and
+    /// executables and 0644 for other files
+    pub normalize_permissions: bool,
+    /// Timestamp used for reproducible builds, `SOURCE_DATE_EPOCH` or the latest git
+    /// commit time when unset
+    pub source_date_epoch: Option<u64>,
+    /// Remove the AppDir once its AppImage has been created
+    pub clean_appdir: bool,
+    /// Write a `.sha256` file next to every AppImage
+    pub checksum: bool,
+    /// Write a `<name>.AppDir.tar.gz` of the AppDir next to every AppImage
+    pub emit_tarball: bool,
+    /// Copy the icon next to every AppImage as `<name>.png`
+    pub emit_icon: bool,
+    /// Do not report progress while staging and copying libraries
+    pub quiet: bool,
+    /// Only print the libraries that would be bundled with each binary instead of
+    /// creating the AppImages
+    pub list_libs: bool,
+    /// Replace AppImages left by a previous build instead of failing
+    pub force: bool,
+    /// Package the binaries built by an earlier `cargo build` instead of building them
+    pub no_build: bool,
+    /// Package this executable instead of building the binaries of the package
+    pub binary: Option<PathBuf>,
+    /// Directory to stage the AppDirs and write the AppImages in instead of cargo's target
+    /// directory, e.g. when it is read-only
+    pub staging_dir: Option<PathBuf>,
+}
+
+/// How libraries that `ldd` cannot resolve are handled when embedding libraries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingLibs {
+    /// Fail the build
+    #[default]
+    Error,
+    /// Print a warning and package the AppImage anyway
+    Warn,
+}
+
+impl AppImageConfig {
+    /// Create a config for the package at `package_path` using the settings from its
+    /// `[package.metadata.appimage]` table
+    pub fn from_package_path<P: AsRef<Path>>(package_path: P) -> Result<Self> {
+        let (path, manifest) = get_manifest_from_path(&package_path)?;
+        let pkg = manifest
+            .package
+            .context(format!("Cannot load metadata from {CARGO_FNAME}"))?;
+        let mut config = AppImageConfig {
+            package_path: path,
+            ..Default::default()
+        };
+
+        if let Some(t @ Value::Table(_)) = pkg.metadata.as_ref().and_then(|m| m.get("appimage")) {
+            config.read_metadata(&envsubst::expand_table(t)?)?;
+        }
+        for feature in config.feature_assets.keys() {
+            // Optional dependencies are features as well
+            let optional = manifest
+                .dependencies
+                .get(feature)
+                .is_some_and(|dep| dep.optional());
+            if !optional && !manifest.features.contains_key(feature) {
+                bail!("features.{feature} is not a feature of {}", pkg.name);
+            }
+        }
+        Ok(config)
+    }
+
+    fn read_metadata(&mut self, t: &Value) -> Result<()> {
+        if let Some(Value::Array(v)) = t.get("assets") {
+            self.assets = string_array(v);
+        }
+        match t.get("features") {
+            Some(Value::Table(features)) => {
+                for (feature, settings) in features {
+                    match settings.get("assets") {
+                        Some(Value::Array(v)) if v.iter().all(Value::is_str) => {
+                            self.feature_assets.insert(feature.clone(), string_array(v));
+                        }
+                        _ => bail!("features.{feature} must be a table with an assets array"),
+                    }
+                }
+            }
+            Some(_) => bail!("features must be a table of per-feature settings"),
+            None => {}
+        }
+        match t.get("targets") {
+            Some(Value::Array(v)) if v.iter().all(Value::is_str) => self.targets = string_array(v),
+            Some(_) => bail!("targets must be an array of target triples"),
+            None => {}
+        }
+        if let Some(v) = t.get("prefix_package") {
+            self.prefix_package = boolean("prefix_package", v)?;
+        }
+        match t.get("out_dir_assets") {
+            Some(Value::Array(v)) if v.iter().all(Value::is_str) => {
+                self.out_dir_assets = string_array(v);
+                for pattern in &self.out_dir_assets {
+                    if Path::new(pattern).is_absolute() || pattern.split('/').any(|c| c == "..") {
+                        bail!("out_dir_assets {pattern:?} must be relative to OUT_DIR");
+                    }
+                }
+            }
+            Some(_) => bail!("out_dir_assets must be an array of globs"),
+            None => {}
+        }
+        if let Some(dir) = t.get("out_dir_assets_dir") {
+            self.out_dir_assets_dir = Some(appdir_relative("out_dir_assets_dir", dir)?);
+        }
+        if let Some(v) = t.get("strip") {
+            self.strip = boolean("strip", v)?;
+        }
+        if let Some(v) = t.get("upx") {
+            self.upx = boolean("upx", v)?;
+        }
+        match t.get("upx_args") {
+            Some(Value::Array(v)) => self.upx_args = string_array(v),
+            Some(_) => bail!("upx_args must be an array of strings"),
+            None => {}
+        }
+        if let Some(v) = t.get("auto_link") {
+            self.auto_link = boolean("auto_link", v)?;
+        }
+        if let Some(v) = t.get("combine_bins") {
+            self.combine_bins = boolean("combine_bins", v)?;
+        }
+        match t.get("primary_bin") {
+            Some(Value::String(v)) => self.primary_bin = Some(v.clone()),
+            Some(_) => bail!("primary_bin must be the name of a binary"),
+            None => {}
+        }
+        if let Some(Value::Array(v)) = t.get("exclude_bins") {
+            self.exclude_bins = string_array(v);
+        }
+        match t.get("bins") {
+            Some(Value::Array(v)) if !v.is_empty() && v.iter().all(Value::is_str) => {
+                self.include_bins = Some(string_array(v))
+            }
+            Some(_) => bail!("bins must be a non-empty array of binary names"),
+            None => {}
+        }
+        match t.get("version_env") {
+            Some(Value::String(v)) if !v.is_empty() && !v.contains('=') => {
+                self.version_env = Some(v.clone())
+            }
+            Some(_) => bail!("version_env must be the name of an env variable"),
+            None => {}
+        }
+        // `appimagetool_args` is the more descriptive name of `args`
+        match (t.get("args"), t.get("appimagetool_args")) {
+            (Some(_), Some(_)) => bail!("args and appimagetool_args cannot both be set"),
+            (Some(Value::Array(v)), None) | (None, Some(Value::Array(v))) => {
+                self.args = string_array(v)
+            }
+            _ => {}
+        }
+        if let Some(v) = t.get("bundle_manifest") {
+            self.bundle_manifest = boolean("bundle_manifest", v)?;
+        }
+        if let Some(v) = t.get("embed_build_info") {
+            self.embed_build_info = boolean("embed_build_info", v)?;
+        }
+        if let Some(Value::Array(v)) = t.get("mksquashfs_args") {
+            self.mksquashfs_args = string_array(v);
+            for arg in &self.mksquashfs_args {
+                if let Some((_, reason)) = RESERVED_MKSQUASHFS_ARGS.iter().find(|(a, _)| a == arg) {
+                    bail!("mksquashfs_args cannot contain {arg}, {reason}");
+                }
+            }
+        }
+        if let Some(v) = t.get("use_excludelist") {
+            self.use_excludelist = boolean("use_excludelist", v)?;
+        }
+        if let Some(v) = t.get("bundle_cxx_runtime") {
+            self.bundle_cxx_runtime = boolean("bundle_cxx_runtime", v)?;
+        }
+        if let Some(Value::Array(arr)) = t.get("assets_exclude") {
+            self.assets_exclude = string_array(arr)
+                .iter()
+                .map(|s| {
+                    glob::Pattern::new(s)
+                        .with_context(|| format!("assets_exclude {s:?} is not a valid glob"))
+                })
+                .collect::<Result<_>>()?;
+        }
+        match t.get("lib_search_paths") {
+            Some(Value::Array(v)) if v.iter().all(Value::is_str) => {
+                self.lib_search_paths = string_array(v).into_iter().map(PathBuf::from).collect()
+            }
+            Some(_) => bail!("lib_search_paths must be an array of directories"),
+            None => {}
+        }
+        if let Some(Value::Array(arr)) = t.get("auto_link_exclude_list") {
+            self.auto_link_exclude_list = string_array(arr)
+                .iter()
+                .map(|s| {
+                    glob::Pattern::new(s)
+                        .context("Auto-link exclude list item not a valid glob pattern")
+                })
+                .collect::<Result<_>>()?;
+        }
+        match t.get("missing_libs") {
+            Some(Value::String(v)) if v == "error" => self.missing_libs = MissingLibs::Error,
+            Some(Value::String(v)) if v == "warn" => self.missing_libs = MissingLibs::Warn,
+            Some(_) => bail!("missing_libs must be either \"error\" or \"warn\""),
+            None => {}
+        }
+        if let Some(dir) = t.get("lib_dir") {
+            self.lib_dir = Some(appdir_relative("lib_dir", dir)?);
+        }
+        if let Some(v) = t.get("use_package_include") {
+            self.use_package_include = boolean("use_package_include", v)?;
+        }
+        if let Some(dir) = t.get("package_include_dir") {
+            self.package_include_dir = Some(appdir_relative("package_include_dir", dir)?);
+        }
+        match t.get("splash") {
+            Some(Value::String(v)) => self.splash = Some(PathBuf::from(v)),
+            Some(_) => bail!("splash must be the path to an image"),
+            None => {}
+        }
+        if let Some(dest) = t.get("splash_dest") {
+            self.splash_dest = Some(appdir_relative("splash_dest", dest)?);
+        }
+        match t.get("icon") {
+            Some(Value::String(v)) => self.icon = Some(PathBuf::from(v)),
+            Some(_) => bail!("icon must be the path to a PNG image"),
+            None => {}
+        }
+        match t.get("bin_name") {
+            Some(Value::String(v))
+                if !v.trim().is_empty() && !v.contains('/') && v != "." && v != ".." =>
+            {
+                self.bin_name = Some(v.clone())
+            }
+            Some(_) => bail!("bin_name must be a file name without path separators"),
+            None => {}
+        }
+        if let Some(v) = t.get("default_icon") {
+            self.empty_icon = !boolean("default_icon", v)?;
+        }
+        match t.get("exec") {
+            Some(Value::String(v)) => {
+                desktop::check_exec(v)?;
+                self.exec = Some(v.clone());
+            }
+            Some(_) => bail!("exec must be a string, e.g. \"{{bin}} %U\""),
+            None => {}
+        }
+        if let Some(v) = t.get("exec_use_apprun") {
+            self.exec_use_apprun = boolean("exec_use_apprun", v)?;
+        }
+        if let Some(v) = t.get("error_dialog") {
+            self.error_dialog = boolean("error_dialog", v)?;
+        }
+        match t.get("product_name") {
+            Some(Value::String(v)) if !v.trim().is_empty() => self.product_name = Some(v.clone()),
+            Some(_) => bail!("product_name must be a non-empty string"),
+            None => {}
+        }
+        match t.get("name") {
+            Some(Value::String(v)) => self.desktop_name = Some(v.clone()),
+            Some(_) => bail!("name must be a string"),
+            None => {}
+        }
+        match t.get("comment") {
+            Some(Value::String(v)) => self.comment = Some(v.clone()),
+            Some(_) => bail!("comment must be a string"),
+            None => {}
+        }
+        if let Some(v) = t.get("name_localized") {
+            localized_table("name_localized", v, &mut self.name_localized)?;
+        }
+        if let Some(v) = t.get("comment_localized") {
+            localized_table("comment_localized", v, &mut self.comment_localized)?;
+        }
+        if let Some(Value::Array(v)) = t.get("categories") {
+            self.categories = string_array(v);
+        }
+        match t.get("keywords") {
+            Some(Value::Array(v)) => self.keywords = keyword_list("keywords", v)?,
+            Some(_) => bail!("keywords must be an array of strings"),
+            None => {}
+        }
+        match t.get("keywords_localized") {
+            Some(Value::Table(table)) => {
+                for (locale, keywords) in table {
+                    if !desktop::is_locale(locale) {
+                        bail!("keywords_localized has invalid locale {locale:?}, expected e.g. \"de\" or \"pt_BR\"");
+                    }
+                    let key = format!("keywords_localized.{locale}");
+                    let Value::Array(keywords) = keywords else {
+                        bail!("{key} must be an array of strings");
+                    };
+                    self.keywords_localized
+                        .insert(locale.clone(), keyword_list(&key, keywords)?);
+                }
+            }
+            Some(_) => bail!("keywords_localized must be a table of keywords by locale"),
+            None => {}
+        }
+        if let Some(v) = t.get("gtk") {
+            self.gtk = boolean("gtk", v)?;
+        }
+        if let Some(v) = t.get("gpu_env") {
+            self.gpu_env = boolean("gpu_env", v)?;
+        }
+        match t.get("env") {
+            Some(Value::Table(env)) => {
+                for (key, value) in env {
+                    let Value::String(value) = value else {
+                        bail!("env value of {key} must be a string");
+                    };
+                    self.env.insert(key.clone(), value.clone());
+                }
+            }
+            Some(_) => bail!("env must be a table of env variables"),
+            None => {}
+        }
+        match t.get("profile") {
+            Some(Value::Table(profiles)) => {
+                for (name, profile) in profiles {
+                    if !profile.is_table() {
+                        bail!("profile.{name} must be a table of settings for the {name} profile");
+                    }
+                    if profile.get("profile").is_some() {
+                        bail!("profile.{name} cannot contain profile tables itself");
+                    }
+                    self.profiles.insert(name.clone(), profile.clone());
+                }
+            }
+            Some(_) => bail!("profile must be a table of per-profile settings"),
+            None => {}
+        }
+        match t.get("bin") {
+            Some(Value::Table(bins)) => {
+                for (name, bin) in bins {
+                    if !bin.is_table() {
+                        bail!("bin.{name} must be a table of settings for the binary {name}");
+                    }
+                    self.bins.insert(name.clone(), bin.clone());
+                }
+            }
+            Some(_) => bail!("bin must be a table of per-binary settings"),
+            None => {}
+        }
+        if let Some(v) = t.get("terminal") {
+            self.terminal = boolean("terminal", v)?;
+        }
+        if let Some(v) = t.get("no_display") {
+            self.no_display = boolean("no_display", v)?;
+        }
+        match t.get("appimage_name") {
+            Some(Value::String(v)) => self.appimage_name = Some(v.clone()),
+            Some(_) => bail!("appimage_name must be a string"),
+            None => {}
+        }
+        match t.get("appimage_version") {
+            Some(Value::String(v)) => self.appimage_version = Some(v.clone()),
+            Some(_) => bail!("appimage_version must be a string"),
+            None => {}
+        }
+        if let Some(v) = t.get("integrate") {
+            self.integrate = Some(boolean("integrate", v)?);
+        }
+        match t.get("desktop_type") {
+            Some(Value::String(v)) if desktop::TYPES.contains(&v.as_str()) => {
+                self.desktop_type = Some(v.clone())
+            }
+            Some(_) => bail!("desktop_type must be one of {}", desktop::TYPES.join(", ")),
+            None => {}
+        }
+        if let Some(v) = t.get("checksum") {
+            self.checksum = boolean("checksum", v)?;
+        }
+        if let Some(v) = t.get("emit_tarball") {
+            self.emit_tarball = boolean("emit_tarball", v)?;
+        }
+        if let Some(v) = t.get("emit_icon") {
+            self.emit_icon = boolean("emit_icon", v)?;
+        }
+        match t.get("post_build") {
+            Some(Value::String(command)) => {
+                self.post_build = vec!["sh".to_string(), "-c".to_string(), command.clone()]
+            }
+            Some(Value::Array(v)) if !v.is_empty() && v.iter().all(Value::is_str) => {
+                self.post_build = string_array(v)
+            }
+            Some(_) => bail!("post_build must be a string or a non-empty array of strings"),
+            None => {}
+        }
+        match t.get("desktop_file") {
+            Some(Value::String(v)) => self.desktop_file = Some(PathBuf::from(v)),
+            Some(_) => bail!("desktop_file must be the path to a .desktop file"),
+            None => {}
+        }
+        match t.get("desktop_spec_version") {
+            Some(Value::String(v)) => self.desktop_spec_version = Some(v.clone()),
+            Some(_) => bail!("desktop_spec_version must be a string, e.g. \"1.0\""),
+            None => {}
+        }
+        match t.get("arch_override") {
+            Some(Value::String(v))
+                if !v.is_empty() && !v.contains(|c: char| c == '/' || c.is_whitespace()) =>
+            {
+                self.arch_override = Some(v.clone())
+            }
+            Some(_) => bail!("arch_override must be an architecture name, e.g. \"armhf\""),
+            None => {}
+        }
+        match t.get("appimagetool_version") {
+            Some(Value::String(v))
+                if !v.is_empty() && !v.contains(|c: char| c == '/' || c.is_whitespace()) =>
+            {
+                self.appimagetool_version = Some(v.clone())
+            }
+            Some(_) => {
+                bail!("appimagetool_version must be a release of appimagetool, e.g. \"1.9.0\"")
+            }
+            None => {}
+        }
+        match t.get("appimagetool_sha256") {
+            Some(Value::String(v)) if v.len() == 64 && v.chars().all(|c| c.is_ascii_hexdigit()) => {
+                self.appimagetool_sha256 = Some(v.to_ascii_lowercase())
+            }
+            Some(_) => bail!("appimagetool_sha256 must be a SHA-256 checksum in hex"),
+            None => {}
+        }
+        match t.get("tool_timeout") {
+            Some(Value::Integer(secs)) if *secs > 0 => {
+                self.tool_timeout = Some(Duration::from_secs(*secs as u64))
+            }
+            Some(_) => bail!("tool_timeout must be a positive number of seconds"),
+            None => {}
+        }
+        match t.get("tool_retries") {
+            Some(Value::Integer(n)) if (0..=u32::MAX as i64).contains(n) => {
+                self.tool_retries = *n as u32
+            }
+            Some(_) => bail!("tool_retries must be a non-negative number"),
+            None => {}
+        }
+        if let Some(v) = t.get("normalize_permissions") {
+            self.normalize_permissions = boolean("normalize_permissions", v)?;
+        }
+        match t.get("reproducible") {
+            Some(Value::Boolean(v)) => self.reproducible = *v,
+            Some(Value::Integer(epoch)) if *epoch >= 0 => {
+                self.reproducible = true;
+                self.source_date_epoch = Some(*epoch as u64);
+            }
+            Some(_) => bail!("reproducible must be a boolean or a unix timestamp"),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Apply the `profile.<name>` table of the cargo profile in `profile`, `release` when
+    /// unset, on top of these settings. Done before applying the command line, which takes
+    /// precedence, while the `bin.<name>` tables are applied on top of it.
+    pub fn apply_profile(&mut self) -> Result<()> {
+        let profile = self
+            .profile
+            .clone()
+            .unwrap_or_else(|| "release".to_string());
+        if let Some(t) = self.profiles.get(&profile).cloned() {
+            self.read_metadata(&t)
+                .with_context(|| format!("Invalid settings for the {profile} profile"))?;
+        }
+        Ok(())
+    }
+
+    /// Config for the binary `name`, with its `bin.<name>` table applied on top
+    fn for_bin(&self, name: &str) -> Result<Self> {
+        let mut config = self.clone();
+        if let Some(t) = self.bins.get(name) {
+            config
+                .read_metadata(t)
+                .with_context(|| format!("Invalid settings for binary {name}"))
+                .context(Failure::Config)?;
+        }
+        Ok(config)
+    }
+
+    /// Whether the binary `name` is packaged according to `bins` and `exclude_bins`
+    fn packages_bin(&self, name: &str) -> bool {
+        match &self.include_bins {
+            Some(include_bins) => include_bins.iter().any(|bin| bin == name),
+            None => !self.exclude_bins.iter().any(|bin| bin == name),
+        }
+    }
+
+    /// Assets of the features `cargo build` enables with the forwarded `cargo_args`
+    fn enabled_feature_assets(&self) -> Result<Vec<String>> {
+        if self.feature_assets.is_empty() {
+            return Ok(vec![]);
+        }
+        let (_, manifest) = get_manifest_from_path(&self.package_path)?;
+        let package = manifest
+            .package
+            .as_ref()
+            .map_or("", |pkg| pkg.name.as_str());
+        let enabled = features::enabled(package, &manifest.features, &self.cargo_args);
+        Ok(self
+            .feature_assets
+            .iter()
+            .filter(|(feature, _)| enabled.contains(*feature))
+            .flat_map(|(_, assets)| assets.iter().cloned())
+            .collect())
+    }
+
+    /// Resolve the asset, icon, splash, desktop file and library search paths against the
+    /// package root
+    /// `root` and return the assets to copy and the asset globs, failing with every path
+    /// that does not exist and every glob that matches no file
+    fn resolve_paths(&mut self, root: &Path) -> Result<(Vec<PathBuf>, Vec<String>)> {
+        let mut missing = vec![];
+        let mut assets = vec![];
+        let mut asset_globs = vec![];
+        for asset in self.assets.iter().chain(&self.enabled_feature_assets()?) {
+            if !asset.contains(&['*', '?', '['][..]) {
+                match root.join(asset).canonicalize() {
+                    Ok(resolved) => assets.push(resolved),
+                    Err(_) => missing.push(asset.clone()),
+                }
+                continue;
+            }
+            if Path::new(asset).is_absolute() || asset.split('/').any(|c| c == "..") {
+                bail!("Asset glob {asset:?} must be relative to the package root");
+            }
+            let root = root.to_str().with_context(|| {
+                format!(
+                    "Asset globs need a package root that is valid Unicode, {} is not",
+                    root.display()
+                )
+            })?;
+            let pattern = format!("{}/{asset}", glob::Pattern::escape(root));
+            let mut paths = glob::glob(&pattern)
+                .with_context(|| format!("Asset {asset:?} is not a valid glob"))?;
+            if paths.any(|path| path.is_ok_and(|path| path.is_file())) {
+                asset_globs.push(asset.clone());
+            } else {
+                missing.push(format!("{asset} (matches no file)"));
+            }
+        }
+        let mut resolve = |path: &Path| match root.join(path).canonicalize() {
+            Ok(resolved) => Some(resolved),
+            Err(_) => {
+                missing.push(path.display().to_string());
+                None
+            }
+        };
+        for path in vec![&mut self.icon, &mut self.splash, &mut self.desktop_file]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(resolved) = resolve(path) {
+                *path = resolved;
+            }
+        }
+        for path in &mut self.lib_search_paths {
+            if let Some(resolved) = resolve(path) {
+                *path = resolved;
+            }
+        }
+        if !missing.is_empty() {
+            bail!(
+                "These paths do not exist in the package root {}:\n  {}",
+                root.display(),
+                missing.join("\n  ")
+            );
+        }
+        Ok((assets, asset_globs))
+    }
+}
+
+/// Check that the value of `key` is a boolean
+fn boolean(key: &str, value: &Value) -> Result<bool> {
+    match value {
+        Value::Boolean(v) => Ok(*v),
+        _ => bail!("{key} must be a boolean"),
+    }
+}
+
+/// Check that the value of `key` is a path inside the AppDir
+fn appdir_relative(key: &str, value: &Value) -> Result<String> {
+    let Value::String(dir) = value else {
+        bail!("{key} must be a string");
+    };
+    if Path::new(dir).is_absolute() || dir.split('/').any(|c| c == "..") {
+        bail!("{key} must be a path inside the AppDir, got {dir:?}");
+    }
+    Ok(dir.trim_end_matches('/').to_string())
+}
+
+/// Read a table of translations keyed by locale into `localized`
+fn localized_table(
+    key: &str,
+    value: &Value,
+    localized: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    let Value::Table(table) = value else {
+        bail!("{key} must be a table of translations by locale");
+    };
+    for (locale, text) in table {
+        if !desktop::is_locale(locale) {
+            bail!("{key} has invalid locale {locale:?}, expected e.g. \"de\" or \"pt_BR\"");
+        }
+        let Value::String(text) = text else {
+            bail!("{key}.{locale} must be a string");
+        };
+        localized.insert(locale.clone(), text.clone());
+    }
+    Ok(())
+}
+
+/// Read the desktop entry keywords of `key`, which are separated by semicolons in the entry
+fn keyword_list(key: &str, v: &[Value]) -> Result<Vec<String>> {
+    v.iter()
+        .map(|keyword| match keyword {
+            Value::String(keyword) if !keyword.trim().is_empty() && !keyword.contains(';') => {
+                Ok(keyword.clone())
+            }
+            _ => bail!("{key} must only contain non-empty strings without semicolons"),
+        })
+        .collect()
+}
+
+/// Keep the strings of a TOML array, skipping any other values
+fn string_array(v: &[Value]) -> Vec<String> {
+    v.iter()
+        .filter_map(|v| match v {
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Return the package path selected by the `CARGO_APPIMAGE_PACKAGE_PATH` or
+/// `CARGO_APPIMAGE_PACKAGE` env variables, defaulting to the nearest directory with a
+/// manifest, starting from the current dir and going up like cargo does
+pub fn get_package_path() -> Result<PathBuf> {
+    if let Ok(env_package) = std::env::var(CARGO_APPIMAGE_PACKAGE_PATH) {
+        return Ok(PathBuf::from(env_package));
+    }
+    let current_dir = std::env::current_dir().context("Could not get current dir")?;
+    if let Ok(package_name) = std::env::var(CARGO_APPIMAGE_PACKAGE) {
+        return Ok(current_dir.join(package_name));
+    }
+    current_dir
+        .ancestors()
+        .find(|dir| dir.join(CARGO_FNAME).is_file())
+        .map(Path::to_path_buf)
+        .with_context(|| {
+            format!(
+                "Could not find {CARGO_FNAME} in {} or any parent directory",
+                current_dir.display()
+            )
+        })
+}
+
+/// Return path to a package manifest and it's manifest from path.
+///
+/// The path can either be a directory or the path to manifest
+pub fn get_manifest_from_path<P: AsRef<Path>>(
+    package_path: P,
+) -> Result<(PathBuf, cargo_toml::Manifest)> {
+    let package_path = if package_path.as_ref().is_dir() {
+        package_path.as_ref().join(CARGO_FNAME)
+    } else {
+        package_path.as_ref().to_path_buf()
+    };
+    let manifest = cargo_toml::Manifest::from_path(&package_path).context(format!(
+        "Could not load manifest from path: {package_path:?}"
+    ))?;
+    Ok((package_path, manifest))
+}
+
+/// Return the manifest paths of the members of the workspace containing the package at
+/// `package_path` that have binaries to package
+pub fn workspace_members<P: AsRef<Path>>(
+    package_path: P,
+    cargo_args: &[String],
+) -> Result<Vec<PathBuf>> {
+    let manifest_path = if package_path.as_ref().is_dir() {
+        package_path.as_ref().join(CARGO_FNAME)
+    } else {
+        package_path.as_ref().to_path_buf()
+    };
+    let metadata = metadata_command(cargo_args)
+        .manifest_path(&manifest_path)
+        .no_deps()
+        .exec()
+        .context("Failed to execute cargo metadata")?;
+
+    let mut members = vec![];
+    for package in metadata
+        .packages
+        .iter()
+        .filter(|p| metadata.workspace_members.contains(&p.id))
+    {
+        if package
+            .targets
+            .iter()
+            .any(|t| t.kind.iter().any(|k| k == "bin"))
+        {
+            members.push(PathBuf::from(package.manifest_path.clone()));
+        } else {
+            diag::note(format_args!(
+                "{} has no binaries, skipping it",
+                package.name
+            ));
+        }
+    }
+    Ok(members)
+}
+
+/// Check that `binary` is an executable ELF file and return its file name
+fn prebuilt_binary_name(binary: &Path) -> Result<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = std::fs::metadata(binary)
+        .with_context(|| format!("Cannot find binary file at {}", binary.display()))?;
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        bail!("{} is not an executable file", binary.display());
+    }
+    if !is_elf(binary)? {
+        bail!("{} is not an ELF binary", binary.display());
+    }
+    binary
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+        .with_context(|| format!("{} has no valid file name", binary.display()))
+}
+
+/// Names of the binaries picked with `--bin` in the cargo arguments
+fn selected_bins(cargo_args: &[String]) -> Vec<String> {
+    let mut selected = vec![];
+    let mut args = cargo_args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(name) = arg.strip_prefix("--bin=") {
+            selected.push(name.to_string());
+        } else if arg == "--bin" {
+            selected.extend(args.next().cloned());
+        }
+    }
+    selected
+}
+
+/// `cargo metadata` command that respects the network and lockfile flags and the
+/// `--target-dir` given to cargo, so the target directory it reports is the one built in
+fn metadata_command(cargo_args: &[String]) -> cargo_metadata::MetadataCommand {
+    let mut options = vec![];
+    let mut args = cargo_args.iter();
+    while let Some(arg) = args.next() {
+        if ["--offline", "--frozen", "--locked"].contains(&arg.as_str()) {
+            options.push(arg.clone());
+        }
+        let target_dir = match arg.strip_prefix("--target-dir=") {
+            Some(dir) => Some(dir),
+            None if arg == "--target-dir" => args.next().map(String::as_str),
+            None => None,
+        };
+        // cargo metadata has no --target-dir, the config key is what it maps to
+        if let Some(dir) = target_dir {
+            options.push("--config".to_string());
+            options.push(format!(
+                "build.target-dir={}",
+                Value::String(dir.to_string())
+            ));
+        }
+    }
+    let mut command = cargo_metadata::MetadataCommand::new();
+    command.other_options(options);
+    command
+}
+
+/// Get the app runner binary installed by Cargo.
+fn get_app_runner_binary_path() -> Result<PathBuf> {
+    let path = PathBuf::from(std::env::var("HOME").context("Could not get home path")?)
+        .join(std::env::var("CARGO_HOME").unwrap_or_else(|_| ".cargo".to_string()))
+        .join("bin")
+        .join(APPIMAGE_RUNNER);
+    if !path.is_file() {
+        Err(anyhow!("Could not get appimage runner from install dir"))
+    } else {
+        Ok(path)
+    }
+}
+
+/// Whether the executable `tool` can be found in `PATH`
+fn in_path(tool: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| {
+            std::fs::metadata(dir.join(tool)).is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            })
+        })
+    })
+}
+
+/// Check for the ELF magic bytes, only ELF binaries can have libraries staged
+fn is_elf<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let mut magic = [0; 4];
+    let mut file = std::fs::File::open(&path)
+        .with_context(|| format!("Cannot find binary file at {}", path.as_ref().display()))?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == *b"\x7fELF"),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Could not read {}", path.as_ref().display())),
+    }
+}
+
+fn stage_libs(
+    lib_dir_staged: &Path,
+    binary_path: &Path,
+    name: &str,
+    missing_libs: MissingLibs,
+    search_paths: &[PathBuf],
+    quiet: bool,
+) -> Result<Vec<PathBuf>> {
+    if !lib_dir_staged.exists() {
+        std::fs::create_dir(lib_dir_staged).context("Could not create libs directory")?;
+    }
+    // The dynamic loader searches LD_LIBRARY_PATH before the RUNPATH of the binary and the
+    // system directories, the search paths come before the ones already set
+    let existing = std::env::var_os("LD_LIBRARY_PATH").unwrap_or_default();
+    let library_path = std::env::join_paths(
+        search_paths
+            .iter()
+            .cloned()
+            .chain(std::env::split_paths(&existing)),
+    )
+    .context("lib_search_paths cannot contain ':'")?;
+    // The C locale keeps the output of ldd in the format parsed below
+    let ldd_output = std::process::Command::new("ldd")
+        .arg(binary_path)
+        .env("LC_ALL", "C")
+        .env("LD_LIBRARY_PATH", library_path)
+        .output()
+        .with_context(|| format!("Failed to run ldd on {}", binary_path.display()))?;
+    let ldd_output = String::from_utf8_lossy(&ldd_output.stdout);
+
+    let missing = not_found_libs(&ldd_output);
+    if !missing.is_empty() {
+        let message = format!(
+            "{name} needs libraries that were not found on this system, install the packages providing them:\n  {}",
+            missing.join("\n  ")
+        );
+        match missing_libs {
+            MissingLibs::Error => bail!(message),
+            MissingLibs::Warn => diag::warning(message),
+        }
+    }
+
+    let mut linkedlibs = ldd_paths(&ldd_output);
+    linkedlibs.sort_unstable();
+    linkedlibs.dedup();
+
+    if !quiet {
+        diag::info(format_args!(
+            "Found {} shared libraries linked by {name}",
+            linkedlibs.len()
+        ));
+    }
+    link_libs(lib_dir_staged, &linkedlibs)
+}
+
+/// Link the libraries at the absolute paths `linkedlibs` into `lib_dir_staged` and return
+/// the links created. Libraries another binary of the AppDir already linked are skipped,
+/// so the ones shared by combined binaries are only bundled once.
+fn link_libs(lib_dir_staged: &Path, linkedlibs: &[&str]) -> Result<Vec<PathBuf>> {
+    let mut libs = vec![];
+    for line in linkedlibs {
+        let staged_path = lib_dir_staged.join(
+            Path::new(line)
+                .file_name()
+                .with_context(|| format!("No filename for {}", line))?,
+        );
+        // The link itself is checked, not the library it points to
+        if staged_path.symlink_metadata().is_ok() {
+            continue;
+        }
+        std::os::unix::fs::symlink(line, &staged_path)
+            .with_context(|| format!("Error symlinking {} to {}", line, staged_path.display()))?;
+        libs.push(staged_path);
+    }
+    Ok(libs)
+}
+
+/// Reject binary and product names that collide with the files cargo-appimage puts in the
+/// root of the AppDir or that are not a single path component
+fn check_bin_name(name: &str, product_name: Option<&str>) -> Result<()> {
+    let reserved = [
+        "usr",
+        "libs",
+        "AppRun",
+        "icon.png",
+        DESKTOP_FILE,
+        runner_config::FILE_NAME,
+    ];
+    for (kind, name) in [("binary", Some(name)), ("product name", product_name)] {
+        let Some(name) = name else {
+            continue;
+        };
+        if reserved.contains(&name) {
+            bail!("The {kind} {name:?} collides with the {name} entry of the AppDir, rename it");
+        }
+        if name.contains(['/', '\0']) || name == "." || name == ".." {
+            bail!("The {kind} {name:?} is not a valid file name");
+        }
+    }
+    Ok(())
+}
+
+/// Absolute paths of the libraries in the output of `ldd`. Virtual libraries provided by
+/// the kernel, the `statically linked` note and libraries ldd could not resolve have
+/// nothing to bundle and are skipped.
+fn ldd_paths(ldd_output: &str) -> Vec<&str> {
+    ldd_output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("linux-vdso") && !line.starts_with("linux-gate"))
+        .filter_map(|line| {
+            // `name => path (address)`, or `path (address)` for the dynamic loader. The
+            // address comes last, so paths containing spaces are kept whole.
+            let path = line.split_once(" => ").map_or(line, |(_, path)| path);
+            let path = path.rfind(" (").map_or(path, |end| &path[..end]).trim();
+            path.starts_with('/').then_some(path)
+        })
+        .collect()
+}
+
+/// Names of the libraries `ldd` reported as `not found`
+fn not_found_libs(ldd_output: &str) -> Vec<String> {
+    ldd_output
+        .lines()
+        .filter_map(|line| line.trim().strip_suffix("=> not found"))
+        .map(|lib| lib.trim().to_string())
+        .collect()
+}
+
+/// Copy the files matching the `include` globs of the package to `dest`, keeping their
+/// path relative to the package root
+fn copy_package_include(include: &[String], dest: &Path) -> Result<()> {
+    // Like in .gitignore a leading slash only anchors the pattern to the package root
+    let include: Vec<String> = include
+        .iter()
+        .map(|pattern| pattern.trim_start_matches('/').to_string())
+        .collect();
+    copy_matching(&include, &[], "Package include item", dest)
+}
+
+/// Copy the files matching the relative `patterns`, but none of `exclude`, to `dest`,
+/// keeping their path relative to the package root
+fn copy_matching(
+    patterns: &[String],
+    exclude: &[glob::Pattern],
+    what: &str,
+    dest: &Path,
+) -> Result<()> {
+    for pattern in patterns {
+        let paths = glob::glob(pattern)
+            .with_context(|| format!("{what} {pattern:?} is not a valid glob"))?;
+        for path in paths {
+            let path = path?;
+            if !path.is_file() || exclude.iter().any(|p| p.matches_path(&path)) {
+                continue;
+            }
+            let file_dest = dest.join(&path);
+            if let Some(parent) = file_dest.parent() {
+                fs_extra::dir::create_all(parent, false)?;
+            }
+            std::fs::copy(&path, &file_dest).with_context(|| {
+                format!(
+                    "Error copying {} to {}",
+                    path.display(),
+                    file_dest.display()
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the files in `out_dir` matched by the globs `patterns` to `dest`, keeping their
+/// path relative to `out_dir`
+fn copy_out_dir_assets(patterns: &[String], out_dir: &Path, dest: &Path) -> Result<()> {
+    let root = out_dir
+        .to_str()
+        .with_context(|| format!("OUT_DIR {} is not valid Unicode", out_dir.display()))?;
+    for pattern in patterns {
+        let paths = glob::glob(&format!("{}/{pattern}", glob::Pattern::escape(root)))
+            .with_context(|| format!("out_dir_assets {pattern:?} is not a valid glob"))?;
+        let mut matched = false;
+        for path in paths {
+            let path = path?;
+            if !path.is_file() {
+                continue;
+            }
+            matched = true;
+            let file_dest = dest.join(path.strip_prefix(out_dir)?);
+            if let Some(parent) = file_dest.parent() {
+                fs_extra::dir::create_all(parent, false)?;
+            }
+            std::fs::copy(&path, &file_dest).with_context(|| {
+                format!(
+                    "Error copying {} to {}",
+                    path.display(),
+                    file_dest.display()
+                )
+            })?;
+        }
+        if !matched {
+            bail!(
+                "out_dir_assets {pattern:?} matches no file in {}",
+                out_dir.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Run the `post_build` hook for the AppImage at `output`
+fn run_post_build(
+    command: &[String],
+    package_root: &Path,
+    output: &Path,
+    appdir: Option<&PathBuf>,
+    name: &str,
+    version: &str,
+) -> Result<()> {
+    let mut hook = Command::new(&command[0]);
+    if let Some(appdir) = appdir {
+        hook.env("CARGO_APPIMAGE_APPDIR", appdir);
+    }
+    let status = hook
+        .args(&command[1..])
+        .current_dir(package_root)
+        .env("CARGO_APPIMAGE_OUTPUT", output)
+        .env("CARGO_APPIMAGE_NAME", name)
+        .env("CARGO_APPIMAGE_VERSION", version)
+        .status()
+        .with_context(|| format!("Could not run post_build command {:?}", command[0]))?;
+    if !status.success() {
+        bail!(
+            "post_build command for {} failed with {status}",
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+/// Names of the primary binaries the AppImages are named after, with the binaries packaged
+/// in each
+type AppImages = Vec<(String, Vec<String>)>;
+
+/// Names of the binaries of the package `pkg` with the targets `bin_targets`, of the ones
+/// packaged and of the AppImages created from them, each named after its primary binary
+/// along with the binaries packaged in it
+fn appimages_of(
+    config: &AppImageConfig,
+    pkg: &cargo_toml::Package,
+    bin_targets: &[cargo_toml::Product],
+) -> Result<(Vec<String>, Vec<String>, AppImages)> {
+    let bins: Vec<String> = bin_targets
+        .iter()
+        .map(|bin| bin.name.clone().unwrap_or(pkg.name.clone()))
+        .collect();
+    for excluded in &config.exclude_bins {
+        if !bins.contains(excluded) {
+            bail!("exclude_bins contains {excluded:?}, which is not a binary of this package");
+        }
+    }
+    if config.include_bins.is_some() && !config.exclude_bins.is_empty() {
+        bail!("bins lists every binary that is packaged, it cannot be combined with exclude_bins");
+    }
+    for included in config.include_bins.iter().flatten() {
+        if !bins.contains(included) {
+            bail!("bins contains {included:?}, which is not a binary of this package");
+        }
+    }
+    for name in config.bins.keys() {
+        if !bins.contains(name) {
+            bail!("[package.metadata.appimage.bin.{name}] does not match a binary of this package");
+        }
+    }
+    // Like cargo, only build and package the binaries picked with --bin when there are any
+    let selected = selected_bins(&config.cargo_args);
+    let packaged: Vec<String> = match &config.binary {
+        // A given binary is packaged on its own, under its file name
+        Some(binary) => vec![prebuilt_binary_name(binary)?],
+        None => bins
+            .iter()
+            .filter(|name| config.packages_bin(name))
+            .filter(|name| selected.is_empty() || selected.contains(name))
+            .cloned()
+            .collect(),
+    };
+    // Each AppImage is named after its primary binary, with `combine_bins` every packaged
+    // binary goes into the one of the primary binary of the package
+    let appimages: AppImages = if config.combine_bins && packaged.len() > 1 {
+        let primary = config
+            .primary_bin
+            .clone()
+            .or_else(|| pkg.default_run.clone())
+            .context("combine_bins needs primary_bin or default-run to pick the binary the desktop entry starts")?;
+        if !packaged.contains(&primary) {
+            bail!("The primary binary {primary} is not one of the packaged binaries");
+        }
+        vec![(primary, packaged.clone())]
+    } else {
+        packaged
+            .iter()
+            .map(|name| (name.clone(), vec![name.clone()]))
+            .collect()
+    };
+    if config.appimage_file_name.is_some() && appimages.len() > 1 {
+        bail!(
+            "An AppImage name was given but {} AppImages are created, pick a binary with --bin",
+            appimages.len()
+        );
+    }
+    Ok((bins, packaged, appimages))
+}
+
+/// `bytes` in the largest binary unit it reaches, e.g. `12.3 MiB`
+fn human_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{bytes} B"),
+                unit => format!("{size:.1} {unit}"),
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1} GiB")
+}
+
+/// Directory holding the staged AppDirs and the `appimage` directory with the AppImages,
+/// `staging_dir` when set and cargo's `target_directory` otherwise
+pub(crate) fn output_root(config: &AppImageConfig, target_directory: &Path) -> PathBuf {
+    config
+        .staging_dir
+        .clone()
+        .unwrap_or_else(|| target_directory.to_path_buf())
+}
+
+/// Fail right away when nothing can be written in `dir`, creating it if needed, instead
+/// of halfway through staging
+fn ensure_writable(dir: &Path) -> Result<()> {
+    let hint = "pass --staging-dir, or --target-dir to cargo, with a writable directory";
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Could not create {}, {hint}", dir.display()))?;
+    let probe = dir.join(".cargo-appimage-write-test");
+    std::fs::File::create(&probe)
+        .with_context(|| format!("{} is not writable, {hint}", dir.display()))?;
+    std::fs::remove_file(&probe).with_context(|| format!("Could not remove {}", probe.display()))
+}
+
+/// Path in `out_dir` of the AppImage, or the squashfs image, named after the binary `name`
+/// of the package `package` and built for `arch`
+fn output_path(
+    config: &AppImageConfig,
+    package: &str,
+    name: &str,
+    out_dir: &Path,
+    arch: &str,
+) -> PathBuf {
+    let file_stem = match (&config.appimage_file_name, &config.product_name) {
+        (Some(file_stem), _) | (None, Some(file_stem)) => file_stem.clone(),
+        (None, None) if config.prefix_package => format!("{package}-{name}"),
+        (None, None) => name.to_string(),
+    };
+    let file_stem = if config.name_with_arch {
+        format!("{file_stem}-{arch}")
+    } else {
+        file_stem
+    };
+    let extension = if config.squashfs_only {
+        "squashfs"
+    } else {
+        "AppImage"
+    };
+    out_dir.join(format!("{file_stem}.{extension}"))
+}
+
+/// Name of the AppDir the binary `name` of the package `package` is staged in
+fn appdir_name(config: &AppImageConfig, package: &str, name: &str) -> String {
+    if config.prefix_package {
+        format!("{package}-{name}.AppDir")
+    } else {
+        format!("{name}.AppDir")
+    }
+}
+
+/// Build the package described by `config` and package each of its binaries as an
+/// AppImage, returning the paths of the AppImages produced
+pub fn build_appimage(mut config: AppImageConfig) -> Result<Vec<PathBuf>> {
+    if config.target.is_none() && !config.targets.is_empty() {
+        if config.targets.len() > 1 {
+            if let Some(arch) = &config.arch_override {
+                return Err(anyhow!(
+                    "arch_override = {arch:?} would give the AppImages of every target the same name, it cannot be used with several targets"
+                )
+                .context(Failure::Config));
+            }
+        }
+        // The AppImages are told apart by their architecture
+        for (i, target) in config.targets.iter().enumerate() {
+            let arch = arch::appimage_arch(Some(target));
+            if let Some(other) = config.targets[..i]
+                .iter()
+                .find(|other| arch::appimage_arch(Some(other)) == arch)
+            {
+                return Err(anyhow!(
+                    "The targets {other} and {target} are both {arch}, their AppImages would get the same name"
+                )
+                .context(Failure::Config));
+            }
+        }
+        let mut outputs = vec![];
+        for target in &config.targets {
+            let mut config = config.clone();
+            config.target = Some(target.clone());
+            config.name_with_arch = config.targets.len() > 1;
+            outputs.extend(
+                build_appimage(config)
+                    .with_context(|| format!("Could not package for {target}"))?,
+            );
+        }
+        return Ok(outputs);
+    }
+    let (path, meta) = get_manifest_from_path(&config.package_path).context(Failure::Config)?;
+    let path = path.canonicalize().context("Could not canonicalize path")?;
+    diag::info(format_args!("Found manifest: {path:?}"));
+    let parent = path.parent().context("Package path has no parent")?;
+    diag::info(format_args!("Moving into package root: {parent:?}"));
+    std::env::set_current_dir(parent).context("Could not chdir to package root")?;
+    let pkg = meta
+        .package
+        .context(format!("Cannot load metadata from {CARGO_FNAME}"))?;
+    config.resolve_paths(parent).context(Failure::Config)?;
+
+    let cargo_metadata = metadata_command(&config.cargo_args)
+        .exec()
+        .context("Failed to execute cargo metadata")
+        .context(Failure::Metadata)?;
+    let target_prefix = cargo_metadata.target_directory;
+    let root_manifest = PathBuf::from(cargo_metadata.workspace_root).join(CARGO_FNAME);
+    // AppDirs are staged in a directory of their own so nothing cargo puts in the target
+    // dir is ever touched. They are kept until the next run unless `clean_appdir` is set
+    // so they can be inspected after the build.
+    let output_root = output_root(&config, Path::new(&target_prefix));
+    let target_stage_dir = output_root.join(STAGING_DIR);
+    let appimage_dir = output_root.join("appimage");
+    if !config.list_libs {
+        ensure_writable(&appimage_dir).context(Failure::Staging)?;
+    }
+    ensure_writable(&target_stage_dir).context(Failure::Staging)?;
+
+    let (bins, packaged, appimages) =
+        appimages_of(&config, &pkg, &meta.bin).context(Failure::Config)?;
+
+    // Paths set in the tables of the binaries are checked before anything is built, so all
+    // of them can be fixed at once instead of failing after the first AppImage is created
+    let mut missing = vec![];
+    for name in packaged
+        .iter()
+        .filter(|name| config.bins.contains_key(*name))
+    {
+        if let Err(e) = config.for_bin(name)?.resolve_paths(parent) {
+            missing.push(format!("[package.metadata.appimage.bin.{name}] {e}"));
+        }
+    }
+    if !missing.is_empty() {
+        return Err(anyhow!("{}", missing.join("\n")).context(Failure::Config));
+    }
+
+    let build_target = arch::build_target(
+        config.target.as_deref(),
+        std::env::var("CARGO_BUILD_TARGET").ok(),
+    );
+    let target_arch = arch::appimage_arch(build_target.as_deref());
+    let appimage_arch =
+        arch::packaged_arch(config.arch_override.as_deref(), build_target.as_deref());
+    let host_arch = arch::appimage_arch(None);
+    // ldd can only load binaries this machine can run, the libraries of other
+    // architectures cannot be found to bundle them
+    if target_arch != host_arch {
+        for name in &packaged {
+            if config.for_bin(name)?.auto_link {
+                return Err(anyhow!(
+                    "auto_link cannot find the libraries of {target_arch} binaries on this {host_arch} machine, set auto_link = false for {name} or package it on a {target_arch} machine"
+                )
+                .context(Failure::Config));
+            }
+        }
+    }
+
+    // Fetched before building so a failed download does not waste a build
+    let appimagetool_path = if config.squashfs_only || config.list_libs {
+        PathBuf::from("appimagetool")
+    } else {
+        fuse::warn_if_missing();
+        appimagetool::locate(
+            config.fetch_appimagetool,
+            config.appimagetool_version.as_deref(),
+            config.appimagetool_sha256.as_deref(),
+            config.quiet,
+        )?
+    };
+
+    // The AppDirs are emptied before building so build scripts can put files in them
+    for name in &packaged {
+        let appdirpath = target_stage_dir.join(appdir_name(&config, &pkg.name, name));
+        if appdirpath.exists() {
+            std::fs::remove_dir_all(&appdirpath)
+                .with_context(|| format!("Could not remove {}", appdirpath.display()))?;
+        }
+    }
+
+    // Create and execute cargo build command.
+    let mut command = Command::new("cargo");
+    command.arg("build").arg("--manifest-path").arg(&path);
+    match &config.profile {
+        Some(profile) => command.arg(format!("--profile={profile}")),
+        None => command.arg("--release"),
+    };
+    if let Some(target) = &config.target {
+        command.arg(format!("--target={target}"));
+    }
+    command.env("CARGO_APPIMAGE_STAGING_DIR", &target_stage_dir);
+    if let [(name, _)] = &appimages[..] {
+        command.env(
+            "CARGO_APPIMAGE_APPDIR",
+            target_stage_dir.join(appdir_name(&config, &pkg.name, name)),
+        );
+    }
+    let built = if let Some(binary) = &config.binary {
+        cargo_build::Built {
+            executables: std::iter::once((packaged[0].clone(), binary.clone())).collect(),
+            ..Default::default()
+        }
+    } else if config.no_build {
+        cargo_build::Built {
+            executables: cargo_build::existing(
+                Path::new(&target_prefix),
+                arch::build_target(
+                    config.target.as_deref(),
+                    std::env::var("CARGO_BUILD_TARGET").ok(),
+                )
+                .as_deref(),
+                config.profile.as_deref(),
+                &packaged,
+            )
+            .context(Failure::Build)?,
+            ..Default::default()
+        }
+    } else {
+        cargo_build::run(command, &config.cargo_args).context(Failure::Build)?
+    };
+    let cargo_build::Built {
+        executables,
+        out_dirs,
+    } = built;
+    // Only the build script of the package itself is looked at for out_dir_assets
+    let out_dir = cargo_metadata
+        .packages
+        .iter()
+        .find(|package| package.manifest_path.as_std_path() == path)
+        .and_then(|package| out_dirs.get(&package.id.repr))
+        .cloned();
+
+    let source_date_epoch = if config.reproducible {
+        Some(reproducible::source_date_epoch(config.source_date_epoch)?)
+    } else {
+        None
+    };
+    let mut outputs = vec![];
+    let version_env = config
+        .version_env
+        .as_deref()
+        .unwrap_or(CARGO_APPIMAGE_VERSION);
+    let version = match std::env::var(version_env) {
+        Ok(version) if !version.is_empty() => version,
+        _ => pkg.version().to_string(),
+    };
+
+    for name in bins.iter().filter(|name| !config.packages_bin(name)) {
+        diag::info(format_args!("Skipping excluded binary {name}"));
+    }
+    for (name, members) in appimages {
+        let members = members
+            .iter()
+            .map(|member| Ok((member, config.for_bin(member)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut config = config.for_bin(&name)?;
+        let (assets, asset_globs) = config.resolve_paths(parent).context(Failure::Config)?;
+        for (member, member_config) in &members {
+            check_bin_name(member, member_config.product_name.as_deref())
+                .context(Failure::Config)?;
+        }
+        let mut exclude_list = config.auto_link_exclude_list.clone();
+        if config.use_excludelist {
+            exclude_list.extend(excludelist::patterns());
+        }
+        let appdirpath = target_stage_dir.join(appdir_name(&config, &pkg.name, &name));
+        fs_extra::dir::create_all(appdirpath.join("usr/bin"), false)
+            .with_context(|| format!("Error creating {}", appdirpath.join("usr/bin").display()))?;
+
+        // The libraries of all binaries in the AppDir are staged together, so the ones they
+        // share are only bundled once
+        let lib_dir_staged = appdirpath.join("libs");
+        let mut binary_paths = vec![];
+        for (member, member_config) in &members {
+            let binary_path = executables.get(*member).with_context(|| {
+                let built = match executables.keys().cloned().collect::<Vec<_>>() {
+                    built if built.is_empty() => "no binaries".to_string(),
+                    built => built.join(", "),
+                };
+                format!(
+                    "cargo did not build the binary {member}, only {built}\n  \
+                     Does it have required-features that are not enabled, or is it not selected by the --bin or --package passed?"
+                )
+            })?;
+            if let Some(binary_arch) = arch::elf_arch(binary_path)? {
+                if binary_arch != target_arch {
+                    return Err(anyhow!(
+                        "{} is built for {binary_arch} but the AppImage would be for {target_arch}, pass the matching --target",
+                        binary_path.display()
+                    )
+                    .context(Failure::Build));
+                }
+            }
+            if member_config.auto_link {
+                if is_elf(binary_path)? {
+                    stage_libs(
+                        &lib_dir_staged,
+                        binary_path,
+                        member,
+                        member_config.missing_libs,
+                        &member_config.lib_search_paths,
+                        member_config.quiet,
+                    )
+                    .context("Could not stage libs")
+                    .context(Failure::Staging)?;
+                } else {
+                    diag::note(format_args!(
+                        "{} is not an ELF binary, not linking its libraries",
+                        binary_path.display()
+                    ));
+                }
+            }
+            binary_paths.push((member, binary_path, member_config));
+        }
+
+        let mut bundled_libs = 0;
+        if lib_dir_staged.exists() {
+            let mut staged = vec![];
+            for i in std::fs::read_dir(&lib_dir_staged).context("Could not read libs dir")? {
+                let path = i?.path();
+
+                // Skip if it matches the exclude list, unless it is part of the C++ runtime
+                // that is bundled on purpose
+                if let Some(file_name) = path.file_name().and_then(|p| p.to_str()) {
+                    let forced =
+                        config.bundle_cxx_runtime && excludelist::is_cxx_runtime(file_name);
+                    if !forced && exclude_list.iter().any(|p| p.matches(file_name)) {
+                        continue;
+                    }
+                }
+                let link = std::fs::read_link(&path)
+                    .with_context(|| format!("Error reading link in libs {}", path.display()))?;
+                staged.push(link);
+            }
+            // read_dir order depends on the filesystem, sorting keeps the AppDir and the
+            // bundled libraries list the same on every machine
+            staged.sort();
+            if config.list_libs && !staged.is_empty() {
+                diag::info(format_args!("Libraries bundled with {name}:"));
+                for link in &staged {
+                    diag::info(format_args!(
+                        "{}\t{}",
+                        link.file_name().unwrap_or_default().to_string_lossy(),
+                        link.canonicalize()
+                            .unwrap_or_else(|_| link.clone())
+                            .display()
+                    ));
+                }
+                continue;
+            }
+
+            bundled_libs = staged.len();
+            let mut progress = Progress::new("Copying libraries", staged.len(), !config.quiet);
+            let mut bundled = String::new();
+            for link in &staged {
+                // The AppRun only knows the usual library directories, not the search paths
+                let searched = link
+                    .parent()
+                    .is_some_and(|parent| config.lib_search_paths.iter().any(|p| p == parent));
+                let dest_dir = match &config.lib_dir {
+                    Some(lib_dir) => appdirpath.join(lib_dir),
+                    None if searched => appdirpath.join("usr/lib"),
+                    None => appdirpath.join(
+                        link.parent()
+                            .and_then(|parent| parent.strip_prefix("/").ok())
+                            .with_context(|| {
+                                format!("Lib {} has no parent dir", &link.display())
+                            })?,
+                    ),
+                };
+                fs_extra::dir::create_all(&dest_dir, false).context(Failure::Staging)?;
+                let dest = dest_dir.join(
+                    link.file_name()
+                        .with_context(|| format!("No filename for {}", link.display()))?,
+                );
+                std::fs::copy(link, &dest)
+                    .with_context(|| {
+                        format!("Error copying {} to {}", &link.display(), dest.display())
+                    })
+                    .context(Failure::Staging)?;
+                if config.bundle_manifest {
+                    // ldd resolves libraries by SONAME, so the file it found is named after it
+                    let source = link.canonicalize().unwrap_or_else(|_| link.clone());
+                    bundled += &format!(
+                        "{}\t{}\n",
+                        link.file_name().unwrap_or_default().to_string_lossy(),
+                        source.display()
+                    );
+                }
+                progress.inc();
+            }
+
+            if config.bundle_manifest {
+                let doc_dir = appdirpath.join(format!("usr/share/doc/{name}"));
+                fs_extra::dir::create_all(&doc_dir, false).context(Failure::Staging)?;
+                std::fs::write(doc_dir.join("bundled-libs.txt"), bundled)
+                    .context("Error writing the bundled libraries manifest")
+                    .context(Failure::Staging)?;
+            }
+        }
+
+        if config.list_libs {
+            diag::info(format_args!("No libraries are bundled with {name}"));
+            continue;
+        }
+
+        for (member, binary_path, member_config) in binary_paths {
+            let member_exe = member_config.bin_name.as_deref().unwrap_or(member);
+            let staged_binary = appdirpath.join(format!("usr/bin/{member_exe}"));
+            std::fs::copy(binary_path, &staged_binary)
+                .with_context(|| format!("Cannot find binary file at {}", binary_path.display()))
+                .context(Failure::Staging)?;
+            if member_config.strip && is_elf(&staged_binary)? {
+                let profile = member_config.profile.as_deref().unwrap_or("release");
+                strip::strip(
+                    &staged_binary,
+                    strip::profile_strip(&root_manifest, profile).context(Failure::Config)?,
+                )
+                .context(Failure::Staging)?;
+            }
+            if member_config.upx && is_elf(&staged_binary)? {
+                upx::compress(&staged_binary, &member_config.upx_args).context(Failure::Staging)?;
+            }
+        }
+        let exe_name = config.bin_name.as_deref().unwrap_or(&name);
+
+        let icon_dest_path = appdirpath.join("icon.png");
+        let found_icon =
+            icon::find(parent, &name, config.icon.as_deref()).context(Failure::Config)?;
+        match &found_icon {
+            Some(icon_path) => {
+                let size = icon::png_size(icon_path).context(Failure::Config)?;
+                std::fs::copy(icon_path, &icon_dest_path)
+                    .context(format!("Cannot copy {icon_path:?}"))
+                    .context(Failure::Staging)?;
+                if let Some((width, height)) = size {
+                    match icon::hicolor_path(width, height) {
+                        Some(theme_path) => {
+                            let theme_path = appdirpath.join(theme_path);
+                            if let Some(parent) = theme_path.parent() {
+                                fs_extra::dir::create_all(parent, false)?;
+                            }
+                            std::fs::copy(icon_path, &theme_path)
+                                .context(format!("Cannot copy {icon_path:?}"))
+                                .context(Failure::Staging)?;
+                        }
+                        None => diag::warning(format_args!(
+                            "{} is {width}x{height}, which is not one of the hicolor icon sizes {:?}, it is only put in the root of the AppDir",
+                            icon_path.display(),
+                            icon::HICOLOR_SIZES
+                        )),
+                    }
+                }
+            }
+            None if config.empty_icon => {
+                std::fs::write(&icon_dest_path, [])
+                    .context(format!("Failed to generate {icon_dest_path:?}"))?;
+            }
+            None => {
+                std::fs::write(&icon_dest_path, icon::DEFAULT_ICON)
+                    .context(format!("Failed to generate {icon_dest_path:?}"))?;
+            }
+        }
+        let splash_dest = match &config.splash {
+            Some(splash) => {
+                let dest = config
+                    .splash_dest
+                    .clone()
+                    .unwrap_or_else(|| format!("usr/share/{name}/splash.png"));
+                let dest_path = appdirpath.join(&dest);
+                if let Some(parent) = dest_path.parent() {
+                    fs_extra::dir::create_all(parent, false)?;
+                }
+                std::fs::copy(splash, &dest_path)
+                    .with_context(|| format!("Cannot copy splash image {}", splash.display()))
+                    .context(Failure::Staging)?;
+                Some(dest)
+            }
+            None => None,
+        };
+        let mut env = config.env.clone();
+        if config.gtk {
+            gtk::stage(&appdirpath, &mut env)?;
+        }
+        if config.use_package_include {
+            let dest = match &config.package_include_dir {
+                Some(dir) => appdirpath.join(dir),
+                None => appdirpath.clone(),
+            };
+            let include = pkg
+                .include
+                .get()
+                .context("Could not resolve the include field of [package]")?;
+            copy_package_include(include, &dest)?;
+        }
+        if !config.out_dir_assets.is_empty() {
+            let out_dir = out_dir.as_deref().with_context(|| {
+                format!(
+                    "out_dir_assets needs the OUT_DIR of the build script of {}, which did not run{}",
+                    pkg.name,
+                    if config.no_build || config.binary.is_some() {
+                        " since nothing was built"
+                    } else {
+                        ""
+                    }
+                )
+            })?;
+            let dest = match &config.out_dir_assets_dir {
+                Some(dir) => appdirpath.join(dir),
+                None => appdirpath.clone(),
+            };
+            copy_out_dir_assets(&config.out_dir_assets, out_dir, &dest)
+                .context(Failure::Staging)?;
+        }
+        fs_extra::copy_items(
+            &assets,
+            appdirpath.as_path(),
+            &CopyOptions {
+                overwrite: true,
+                buffer_size: 0,
+                copy_inside: true,
+                ..Default::default()
+            },
+        )
+        .context("Error copying assets")
+        .context(Failure::Staging)?;
+        copy_matching(&asset_globs, &config.assets_exclude, "Asset", &appdirpath)
+            .context(Failure::Staging)?;
+        // Drivers are staged with the assets
+        if config.gpu_env {
+            gpu::configure(&appdirpath, &mut env)?;
+        }
+        if config.embed_build_info {
+            build_info::write(
+                &appdirpath,
+                &name,
+                &version,
+                build_target.as_deref(),
+                source_date_epoch,
+            )
+            .context(Failure::Staging)?;
+        }
+        let desktop_entry = match &config.desktop_file {
+            Some(desktop_file) => desktop::adapt(
+                &std::fs::read_to_string(desktop_file)
+                    .with_context(|| format!("Cannot read desktop file {}", desktop_file.display()))
+                    .context(Failure::Staging)?,
+                exe_name,
+            )
+            .with_context(|| format!("Invalid desktop file {}", desktop_file.display()))
+            .context(Failure::Config)?,
+            None => {
+                categories::check(&config.categories).context(Failure::Config)?;
+                desktop::check_terminal(&config).context(Failure::Config)?;
+                desktop::render(&name, &version, &config)
+            }
+        };
+        std::fs::write(appdirpath.join(DESKTOP_FILE), desktop_entry)
+            .with_context(|| {
+                format!(
+                    "Error writing desktop file {}",
+                    appdirpath.join(DESKTOP_FILE).display()
+                )
+            })
+            .context(Failure::Staging)?;
+        let runner_config = RunnerConfig {
+            lib_dir: config.lib_dir.clone(),
+            splash: splash_dest,
+            env,
+            exec: Some(exe_name.to_string()),
+            error_dialog: config.error_dialog,
+        };
+        std::fs::write(
+            appdirpath.join(runner_config::FILE_NAME),
+            runner_config.render(),
+        )
+        .context("Error writing runner config")
+        .context(Failure::Staging)?;
+        let app_runner_path = get_app_runner_binary_path().context(Failure::MissingTool)?;
+        std::fs::copy(&app_runner_path, appdirpath.join("AppRun"))
+            .with_context(|| {
+                format!(
+                    "Error copying {} to {}",
+                    app_runner_path.display(),
+                    appdirpath.join("AppRun").display()
+                )
+            })
+            .context(Failure::Staging)?;
+
+        let output = output_path(&config, &pkg.name, &name, &appimage_dir, &appimage_arch);
+        if output.exists() {
+            if !config.force {
+                return Err(anyhow!(
+                    "{} already exists, pass --force to overwrite it",
+                    output.display()
+                )
+                .context(Failure::Config));
+            }
+            std::fs::remove_file(&output)
+                .with_context(|| format!("Could not remove {}", output.display()))?;
+        }
+        if config.normalize_permissions {
+            permissions::normalize(&appdirpath)?;
+        }
+        if let Some(epoch) = source_date_epoch {
+            reproducible::normalize_mtimes(&appdirpath, epoch)?;
+        }
+        if config.squashfs_only {
+            squashfs::create(
+                &appdirpath,
+                &output,
+                &config.args,
+                &config.mksquashfs_args,
+                source_date_epoch,
+            )
+            .with_context(|| format!("Could not create {}", output.display()))?;
+        } else {
+            let appimagetool = || {
+                let mut command = Command::new(&appimagetool_path);
+                command.args(&config.args);
+                for arg in &config.mksquashfs_args {
+                    command.arg("--mksquashfs-opt").arg(arg);
+                }
+                command
+                    .arg(&appdirpath)
+                    .arg(&output)
+                    .env("ARCH", &appimage_arch)
+                    .env("VERSION", &version);
+                if let Some(epoch) = source_date_epoch {
+                    command.env("SOURCE_DATE_EPOCH", epoch.to_string());
+                }
+                command
+            };
+            appimagetool::run_with_retries(appimagetool, config.tool_timeout, config.tool_retries)
+                .with_context(|| format!("Could not create {}", output.display()))?;
+        }
+        // The placeholder icons are not worth publishing
+        if config.emit_icon && found_icon.is_some() {
+            let icon_output = output.with_extension("png");
+            std::fs::copy(&icon_dest_path, &icon_output)
+                .with_context(|| format!("Error writing {}", icon_output.display()))?;
+        }
+        if config.emit_tarball {
+            let tarball = output.with_extension("AppDir.tar.gz");
+            tarball::create(&appdirpath, &tarball, source_date_epoch)
+                .with_context(|| format!("Could not create {}", tarball.display()))?;
+        }
+        if config.clean_appdir {
+            std::fs::remove_dir_all(&appdirpath)
+                .with_context(|| format!("Could not remove {}", appdirpath.display()))?;
+        }
+        if config.checksum {
+            checksum::write_sha256(&output)?;
+        }
+        if !config.post_build.is_empty() {
+            run_post_build(
+                &config.post_build,
+                parent,
+                &output,
+                (!config.clean_appdir).then_some(&appdirpath),
+                &name,
+                &version,
+            )?;
+        }
+        let size = std::fs::metadata(&output)
+            .with_context(|| format!("Could not read {}", output.display()))?
+            .len();
+        if cargo_build::json_messages(&config.cargo_args) {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "reason": "appimage-created",
+                    "path": output.to_string_lossy(),
+                    "size": size,
+                    "bundled_libraries": bundled_libs,
+                })
+            );
+        } else {
+            let libraries = match bundled_libs {
+                1 => "1 library".to_string(),
+                n => format!("{n} libraries"),
+            };
+            diag::success(format_args!(
+                "Created {} ({}, {libraries} bundled)",
+                output.display(),
+                human_size(size)
+            ));
+        }
+        outputs.push(output);
+    }
+
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory for a test, removed first when an earlier run left it behind
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("cargo-appimage-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn metadata_respects_offline_flags() {
+        let cargo_args = [
+            "--offline",
+            "--features=gui",
+            "--locked",
+            "--frozen",
+            "--release",
+        ]
+        .map(String::from);
+        let command = metadata_command(&cargo_args).cargo_command();
+        let args: Vec<_> = command.get_args().collect();
+        for flag in ["--offline", "--locked", "--frozen"] {
+            assert!(
+                args.contains(&flag.as_ref()),
+                "{} missing in {:?}",
+                flag,
+                args
+            );
+        }
+        for flag in ["--features=gui", "--release"] {
+            assert!(
+                !args.contains(&flag.as_ref()),
+                "{} passed in {:?}",
+                flag,
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn target_dir_moves_the_staging_root() {
+        let target_dir = test_dir("target-dir").join("my target");
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join(CARGO_FNAME);
+        let dir = target_dir.display().to_string();
+        for cargo_args in [
+            vec![
+                "--offline".to_string(),
+                "--target-dir".to_string(),
+                dir.clone(),
+            ],
+            vec!["--offline".to_string(), format!("--target-dir={dir}")],
+        ]
+        .iter()
+        {
+            let metadata = metadata_command(cargo_args)
+                .manifest_path(&manifest)
+                .no_deps()
+                .exec()
+                .unwrap();
+            let root = output_root(
+                &AppImageConfig::default(),
+                metadata.target_directory.as_std_path(),
+            );
+            assert_eq!(root, target_dir);
+            assert_eq!(root.join(STAGING_DIR), target_dir.join("cargo-appimage"));
+        }
+        std::fs::remove_dir_all(target_dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn metadata_runs_offline() {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join(CARGO_FNAME);
+        let metadata = metadata_command(&["--offline".to_string()])
+            .manifest_path(&manifest)
+            .no_deps()
+            .exec()
+            .unwrap();
+        assert!(metadata
+            .packages
+            .iter()
+            .any(|package| package.name == "cargo-appimage"));
+    }
+
+    /// `ldd` of a binary on glibc, with a library that is not installed
+    const LDD_GLIBC: &str = "\
+\tlinux-vdso.so.1 (0x00007ffc8d1f2000)
+\tlibssl.so.3 => /lib/x86_64-linux-gnu/libssl.so.3 (0x00007f3b6c8a1000)
+\tlibgtk-3.so.0 => not found
+\tlibc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f3b6c600000)
+\t/lib64/ld-linux-x86-64.so.2 (0x00007f3b6cb6d000)
+";
+
+    /// `ldd` of a binary on musl
+    const LDD_MUSL: &str = "\
+\t/lib/ld-musl-x86_64.so.1 (0x7f0c2a3b5000)
+\tlibgcc_s.so.1 => /usr/lib/libgcc_s.so.1 (0x7f0c2a38f000)
+\tlibc.musl-x86_64.so.1 => /lib/ld-musl-x86_64.so.1 (0x7f0c2a3b5000)
+";
+
+    /// `ldd` of an i686 binary on an older glibc, with the vDSO mapped without a path
+    const LDD_OLD_GLIBC: &str = "\
+\tlinux-gate.so.1 =>  (0xb7713000)
+\tlibm.so.6 => /lib/i386-linux-gnu/libm.so.6 (0xb76c0000)
+\tlibc.so.6 => /lib/i386-linux-gnu/libc.so.6 (0xb7500000)
+\t/lib/ld-linux.so.2 (0x80000000)
+";
+
+    /// `ldd` of a binary whose libraries are in a directory with a space
+    const LDD_SPACES: &str = "\
+\tlinux-vdso.so.1 (0x00007ffc8d1f2000)
+\tlibsdk.so.2 => /opt/My SDK/lib (x64)/libsdk.so.2 (0x00007f3b6c8a1000)
+\tlibc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f3b6c600000)
+";
+
+    #[test]
+    fn ldd_paths_of_glibc() {
+        assert_eq!(
+            ldd_paths(LDD_GLIBC),
+            [
+                "/lib/x86_64-linux-gnu/libssl.so.3",
+                "/lib/x86_64-linux-gnu/libc.so.6",
+                "/lib64/ld-linux-x86-64.so.2"
+            ]
+        );
+        assert_eq!(not_found_libs(LDD_GLIBC), ["libgtk-3.so.0"]);
+    }
+
+    #[test]
+    fn ldd_paths_of_musl() {
+        assert_eq!(
+            ldd_paths(LDD_MUSL),
+            [
+                "/lib/ld-musl-x86_64.so.1",
+                "/usr/lib/libgcc_s.so.1",
+                "/lib/ld-musl-x86_64.so.1"
+            ]
+        );
+        assert!(not_found_libs(LDD_MUSL).is_empty());
+    }
+
+    #[test]
+    fn ldd_paths_skip_the_vdso() {
+        assert_eq!(
+            ldd_paths(LDD_OLD_GLIBC),
+            [
+                "/lib/i386-linux-gnu/libm.so.6",
+                "/lib/i386-linux-gnu/libc.so.6",
+                "/lib/ld-linux.so.2"
+            ]
+        );
+    }
+
+    #[test]
+    fn ldd_paths_with_spaces() {
+        assert_eq!(
+            ldd_paths(LDD_SPACES),
+            [
+                "/opt/My SDK/lib (x64)/libsdk.so.2",
+                "/lib/x86_64-linux-gnu/libc.so.6"
+            ]
+        );
+    }
+
+    #[test]
+    fn ldd_paths_of_static_binaries() {
+        assert!(ldd_paths("\tstatically linked\n").is_empty());
+        assert!(ldd_paths("\tnot a dynamic executable\n").is_empty());
+    }
+
+    #[test]
+    fn stage_libs_with_spaces_in_paths() {
+        let dir = test_dir("spaces");
+        let target_dir = dir.join("target dir/release");
+        let appdir = dir.join("target dir/cargo-appimage/my app.AppDir");
+        std::fs::create_dir_all(&target_dir).unwrap();
+        std::fs::create_dir_all(&appdir).unwrap();
+        // The test binary itself is a dynamically linked executable
+        let binary = target_dir.join("my app");
+        std::fs::copy(std::env::current_exe().unwrap(), &binary).unwrap();
+
+        let libs_dir = appdir.join("libs");
+        let staged =
+            stage_libs(&libs_dir, &binary, "my app", MissingLibs::Error, &[], true).unwrap();
+        assert!(!staged.is_empty());
+        for link in &staged {
+            assert_eq!(link.parent(), Some(libs_dir.as_path()));
+            assert!(link.exists(), "{} is dangling", link.display());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_bin_tables_are_config_errors() {
+        let package: Value = toml::from_str("[bin.tool]\nproduct_name = 42").unwrap();
+        let mut config = AppImageConfig::default();
+        config.read_metadata(&package).unwrap();
+        let error = config.for_bin("tool").unwrap_err();
+        assert_eq!(Failure::exit_code_of(&error), 2);
+    }
+
+    #[test]
+    fn non_boolean_switches_are_rejected() {
+        let package: Value = toml::from_str("combine_bins = \"yes\"").unwrap();
+        let error = AppImageConfig::default()
+            .read_metadata(&package)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "combine_bins must be a boolean");
+        let package: Value = toml::from_str("[bin.tool]\nstrip = 1").unwrap();
+        let mut config = AppImageConfig::default();
+        config.read_metadata(&package).unwrap();
+        let error = config.for_bin("tool").unwrap_err();
+        assert_eq!(Failure::exit_code_of(&error), 2);
+    }
+
+    #[test]
+    fn bin_tables_replace_lists() {
+        let package: Value = toml::from_str(
+            r#"
+            auto_link_exclude_list = ["libc.so*", "libm.so*"]
+            [bin.tool]
+            auto_link_exclude_list = ["libssl.so*"]
+            "#,
+        )
+        .unwrap();
+        let mut config = AppImageConfig::default();
+        config.read_metadata(&package).unwrap();
+        let patterns = |config: &AppImageConfig| {
+            config
+                .auto_link_exclude_list
+                .iter()
+                .map(|p| p.as_str().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(patterns(&config), ["libc.so*", "libm.so*"]);
+        assert_eq!(patterns(&config.for_bin("tool").unwrap()), ["libssl.so*"]);
+        assert_eq!(patterns(&config.for_bin("app").unwrap()), patterns(&config));
+    }
+
+    /// File names of the libraries `ldd` finds for `binary`
+    fn linked_libs(binary: &Path) -> std::collections::BTreeSet<String> {
+        let output = Command::new("ldd").arg(binary).output().unwrap();
+        ldd_paths(&String::from_utf8_lossy(&output.stdout))
+            .iter()
+            .filter_map(|path| Path::new(path).file_name()?.to_str().map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn combined_binaries_keep_each_others_libs() {
+        let dir = test_dir("combined-libs");
+        let libs_dir = dir.join("libs");
+        // The test binary itself and a common tool that links libraries it does not
+        let first = std::env::current_exe().unwrap();
+        let first_libs = linked_libs(&first);
+        let path = std::env::var_os("PATH").unwrap();
+        let second = ["ls", "tar", "ssh", "curl", "git", "perl"]
+            .iter()
+            .flat_map(|tool| std::env::split_paths(&path).map(move |dir| dir.join(tool)))
+            .find(|tool| tool.is_file() && !linked_libs(tool).is_subset(&first_libs))
+            .expect("no tool in PATH links libraries the test binary does not");
+        let second_libs = linked_libs(&second);
+
+        stage_libs(&libs_dir, &first, "first", MissingLibs::Warn, &[], true).unwrap();
+        stage_libs(&libs_dir, &second, "second", MissingLibs::Warn, &[], true).unwrap();
+        let staged: std::collections::BTreeSet<String> = std::fs::read_dir(&libs_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(
+            first_libs.is_subset(&staged),
+            "{:?} not in {:?}",
+            first_libs,
+            staged
+        );
+        assert!(
+            second_libs.is_subset(&staged),
+            "{:?} not in {:?}",
+            second_libs,
+            staged
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
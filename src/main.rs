@@ -1,352 +1,563 @@
 use anyhow::{anyhow, bail, Context, Result};
-use cargo_toml::Value;
-use fs_extra::dir::CopyOptions;
+use cargo_appimage::{
+    build_appimage, check_config, diag, get_package_path, init_manifest, plan, workspace_members,
+    AppImageConfig, Failure, CARGO_APPIMAGE_PROFILE,
+};
 use std::{
-    io::{Read, Write},
+    collections::BTreeMap,
+    fs::File,
+    io::Write,
+    os::unix::io::FromRawFd,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    time::Duration,
 };
 
-const CARGO_APPIMAGE_PACKAGE_PATH: &str = "CARGO_APPIMAGE_PACKAGE_PATH";
-const CARGO_APPIMAGE_PACKAGE: &str = "CARGO_APPIMAGE_PACKAGE";
-const CARGO_FNAME: &str = "Cargo.toml";
-const APPIMAGE_RUNNER: &str = "cargo-appimage-runner";
-
-/// Return path to a package manifest and it's manifest
-fn get_manifest() -> Result<(PathBuf, cargo_toml::Manifest)> {
-    let package_path = if let Ok(env_package) = std::env::var(CARGO_APPIMAGE_PACKAGE_PATH) {
-        PathBuf::from(env_package)
-    } else {
-        let package_name = std::env::var(CARGO_APPIMAGE_PACKAGE).unwrap_or_default();
-        std::env::current_dir()
-            .context("Could not get current dir")?
-            .join(package_name)
-    };
-
-    get_manifest_from_path(package_path)
+/// Command line options, these take precedence over env variables and the manifest
+#[derive(Default)]
+struct CliArgs {
+    manifest_path: Option<PathBuf>,
+    workspace: bool,
+    keep_going: bool,
+    print_config: bool,
+    check: bool,
+    print_path: bool,
+    list_libs: bool,
+    force: bool,
+    no_build: bool,
+    fetch_appimagetool: bool,
+    squashfs_only: bool,
+    prefix_package: bool,
+    binary: Option<PathBuf>,
+    staging_dir: Option<PathBuf>,
+    deny_warnings: Option<bool>,
+    appimage_name: Option<String>,
+    tool_timeout: Option<Duration>,
+    tool_retries: Option<u32>,
+    tool_args: Vec<String>,
+    reproducible: bool,
+    normalize_permissions: bool,
+    source_date_epoch: Option<u64>,
+    checksum: bool,
+    emit_tarball: bool,
+    emit_icon: bool,
+    no_default_icon: bool,
+    clean_appdir: Option<bool>,
+    bundle_manifest: bool,
+    embed_build_info: bool,
+    quiet: bool,
+    json_messages: bool,
+    profile: Option<String>,
+    release: bool,
+    debug: bool,
+    targets: Vec<String>,
+    cargo_args: Vec<String>,
 }
 
-/// Return path to a package manifest and it's manifest from path.
-///
-/// The path can either be a directory or the path to manifest
-fn get_manifest_from_path<P: AsRef<Path>>(
-    package_path: P,
-) -> Result<(PathBuf, cargo_toml::Manifest)> {
-    let package_path = if package_path.as_ref().is_dir() {
-        package_path.as_ref().join(CARGO_FNAME)
-    } else {
-        package_path.as_ref().to_path_buf()
-    };
-    let manifest = cargo_toml::Manifest::from_path(&package_path).context(format!(
-        "Could not load manifest from path: {package_path:?}"
-    ))?;
-    Ok((package_path, manifest))
-}
+impl CliArgs {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut cli = CliArgs::default();
 
-/// Get the app runner binary installed by Cargo.
-fn get_app_runner_binary_path() -> Result<PathBuf> {
-    let path = PathBuf::from(std::env::var("HOME").context("Could not get home path")?)
-        .join(std::env::var("CARGO_HOME").unwrap_or_else(|_| ".cargo".to_string()))
-        .join("bin")
-        .join(APPIMAGE_RUNNER);
-    if !path.is_file() {
-        eprintln!("Warning: Could not get appimage runner from install dir");
-        Err(anyhow!("Could not get appimage runner from install dir"))
-    } else {
-        Ok(path)
+        // Arguments not understood by cargo-appimage are redirected to cargo, the profile
+        // and target are picked out as well since they decide where the binaries end up.
+        while let Some(arg) = args.next() {
+            if let Some(value) = flag_value(&arg, "--tool-timeout", &mut args) {
+                let secs = value?
+                    .parse()
+                    .ok()
+                    .filter(|&secs| secs > 0)
+                    .context("--tool-timeout expects a positive number of seconds")?;
+                cli.tool_timeout = Some(Duration::from_secs(secs));
+            } else if let Some(value) = flag_value(&arg, "--tool-retries", &mut args) {
+                cli.tool_retries = Some(
+                    value?
+                        .parse()
+                        .context("--tool-retries expects a number of retries")?,
+                );
+            } else if let Some(value) = flag_value(&arg, "--tool-arg", &mut args) {
+                cli.tool_args.push(value?);
+            } else if let Some(value) = flag_value(&arg, "--appimage-name", &mut args) {
+                cli.appimage_name = Some(value?);
+            } else if let Some(value) = flag_value(&arg, "--binary", &mut args) {
+                // Resolved now since packaging happens in the package root
+                let binary = PathBuf::from(value?);
+                cli.binary =
+                    Some(std::fs::canonicalize(&binary).with_context(|| {
+                        format!("--binary {} does not exist", binary.display())
+                    })?);
+            } else if let Some(value) = flag_value(&arg, "--staging-dir", &mut args) {
+                // Made absolute now since packaging happens in the package root
+                let staging_dir = PathBuf::from(value?);
+                cli.staging_dir = Some(if staging_dir.is_absolute() {
+                    staging_dir
+                } else {
+                    std::env::current_dir()
+                        .context("Could not get the current directory")?
+                        .join(staging_dir)
+                });
+            } else if let Some(value) = flag_value(&arg, "--profile", &mut args) {
+                // The profile decides both the cargo build flags and where the binaries
+                // end up, like cargo a second one is rejected instead of guessing
+                let profile = value?;
+                if let Some(first) = &cli.profile {
+                    bail!("--profile was passed more than once, as {first} and {profile}, pass only one");
+                }
+                cli.profile = Some(profile);
+            } else if let Some(value) = flag_value(&arg, "--target", &mut args) {
+                cli.targets.push(value?);
+            } else if let Some(value) = flag_value(&arg, "--manifest-path", &mut args) {
+                cli.manifest_path = Some(PathBuf::from(value?));
+            } else if arg == "--workspace" || arg == "--all" {
+                cli.workspace = true;
+            } else if arg == "--print-config" {
+                cli.print_config = true;
+            } else if arg == "--check" {
+                cli.check = true;
+            } else if arg == "--print-path" || arg == "--print-appimage-path" {
+                // Progress would end up between the messages on stderr
+                cli.print_path = true;
+                cli.quiet = true;
+            } else if arg == "--deny-warnings" {
+                cli.deny_warnings = Some(false);
+            } else if arg == "--deny-warnings=immediate" {
+                cli.deny_warnings = Some(true);
+            } else if arg == "--prefix-package" {
+                cli.prefix_package = true;
+            } else if arg == "--no-appimage-tool" {
+                cli.squashfs_only = true;
+            } else if arg == "--fetch-appimagetool" {
+                cli.fetch_appimagetool = true;
+            } else if arg == "--no-build" {
+                cli.no_build = true;
+            } else if arg == "--force" {
+                cli.force = true;
+            } else if arg == "--list-libs" {
+                cli.list_libs = true;
+            } else if arg == "--keep-going" {
+                cli.keep_going = true;
+            } else if arg == "--bom" || arg == "--bundle-manifest" {
+                cli.bundle_manifest = true;
+            } else if arg == "--embed-build-info" {
+                cli.embed_build_info = true;
+            } else if arg == "--keep-appdir" {
+                cli.clean_appdir = Some(false);
+            } else if arg == "--clean-appdir" {
+                cli.clean_appdir = Some(true);
+            } else if arg == "--checksum" {
+                cli.checksum = true;
+            } else if arg == "--also-tar" {
+                cli.emit_tarball = true;
+            } else if arg == "--emit-icon" {
+                cli.emit_icon = true;
+            } else if arg == "--no-default-icon" {
+                cli.no_default_icon = true;
+            } else if arg == "--normalize-permissions" {
+                cli.normalize_permissions = true;
+            } else if arg == "--reproducible" {
+                cli.reproducible = true;
+            } else if let Some(epoch) = arg.strip_prefix("--reproducible=") {
+                cli.reproducible = true;
+                cli.source_date_epoch = Some(
+                    epoch
+                        .parse()
+                        .context("--reproducible expects a unix timestamp")?,
+                );
+            } else if arg == "--release" || arg == "-r" {
+                cli.release = true;
+            } else if arg == "--debug" {
+                cli.debug = true;
+            } else {
+                // Cargo's quiet flag and JSON messages are also honored by cargo-appimage,
+                // progress output would only get in the way there.
+                if arg.starts_with("--message-format=json") {
+                    cli.json_messages = true;
+                    cli.quiet = true;
+                } else if arg == "-q" || arg == "--quiet" {
+                    cli.quiet = true;
+                }
+                cli.cargo_args.push(arg);
+            }
+        }
+        if let Some(immediately) = cli.deny_warnings {
+            diag::deny_warnings(immediately);
+        }
+        if cli.release && cli.debug {
+            bail!("--release and --debug cannot be used together");
+        }
+        // --profile takes precedence over --release and --debug, which only pick a profile
+        let implied = [
+            ("--release", cli.release, "release"),
+            ("--debug", cli.debug, "dev"),
+        ];
+        for &(flag, passed, implied) in implied.iter() {
+            match cli.profile.as_deref() {
+                _ if !passed => {}
+                None => {}
+                Some(profile) if profile == implied => {}
+                Some(profile) => diag::warning(format_args!(
+                    "Both {flag} and --profile={profile} were passed, building with the {profile} profile"
+                )),
+            }
+        }
+        Ok(cli)
     }
-}
 
-fn stage_libs<P: AsRef<Path>>(
-    lib_dir_staged: P,
-    target_prefix: P,
-    target: &str,
-    name: &str,
-) -> Result<Vec<PathBuf>> {
-    let lib_dir_staged = lib_dir_staged.as_ref();
-    if !lib_dir_staged.exists() {
-        std::fs::create_dir(lib_dir_staged).context("Could not create libs directory")?;
+    fn package_path(&self) -> Result<PathBuf> {
+        match &self.manifest_path {
+            Some(manifest_path) => {
+                if !manifest_path.is_file() {
+                    bail!(
+                        "--manifest-path {} does not point to a manifest file",
+                        manifest_path.display()
+                    );
+                }
+                Ok(manifest_path.clone())
+            }
+            None => get_package_path(),
+        }
     }
-    let awk = std::process::Command::new("awk")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .arg("NF == 4 {print $3}; NF == 2 {print $1}")
-        .spawn()
-        .context("Could not start awk")?;
 
-    awk.stdin
-        .context("Make sure you have awk on your system")?
-        .write_all(
-            &std::process::Command::new("ldd")
-                .arg(format!(
-                    "{}/{}/{}",
-                    target_prefix.as_ref().display(),
-                    target,
-                    name
-                ))
-                .output()
-                .with_context(|| {
-                    format!(
-                        "Failed to run ldd on {}/{}/{}",
-                        target_prefix.as_ref().display(),
-                        target,
-                        name
-                    )
-                })?
-                .stdout,
-        )?;
-
-    let mut linkedlibs = String::new();
-    awk.stdout
-        .context("Unknown error ocurred while running awk")?
-        .read_to_string(&mut linkedlibs)?;
-
-    fs_extra::dir::create(lib_dir_staged, true).context("Failed to create libs dir")?;
+    fn config(&self, package_path: &Path) -> Result<AppImageConfig> {
+        let mut config =
+            AppImageConfig::from_package_path(package_path).context(Failure::Config)?;
+        config.profile = match (&self.profile, self.debug, self.release) {
+            (Some(profile), _, _) => Some(profile.clone()),
+            (None, true, _) => Some("dev".to_string()),
+            (None, false, true) => None,
+            (None, false, false) => std::env::var(CARGO_APPIMAGE_PROFILE)
+                .ok()
+                .filter(|profile| !profile.is_empty()),
+        };
+        config.apply_profile().context(Failure::Config)?;
 
-    let mut libs = vec![];
-    for line in linkedlibs.lines() {
-        let lib_path = lib_dir_staged.join(&line[1..]);
-        if line.starts_with('/') && !lib_path.exists() {
-            let staged_path = lib_dir_staged.join(
-                std::path::Path::new(line)
-                    .file_name()
-                    .with_context(|| format!("No filename for {}", line))?,
-            );
-            std::os::unix::fs::symlink(line, &staged_path)
-                .with_context(|| format!("Error symlinking {} to {}", line, lib_path.display()))?;
-            libs.push(staged_path);
+        if let Some(appimage_name) = &self.appimage_name {
+            config.appimage_file_name = Some(appimage_name.clone());
+        }
+        if let Some(tool_timeout) = self.tool_timeout {
+            config.tool_timeout = Some(tool_timeout);
+        }
+        if let Some(tool_retries) = self.tool_retries {
+            config.tool_retries = tool_retries;
+        }
+        config.args.extend(self.tool_args.iter().cloned());
+        if self.reproducible {
+            config.reproducible = true;
+        }
+        if self.normalize_permissions {
+            config.normalize_permissions = true;
+        }
+        if let Some(epoch) = self.source_date_epoch {
+            config.source_date_epoch = Some(epoch);
+        }
+        if self.checksum {
+            config.checksum = true;
+        }
+        if self.emit_tarball {
+            config.emit_tarball = true;
+        }
+        if self.emit_icon {
+            config.emit_icon = true;
+        }
+        if self.no_default_icon {
+            config.empty_icon = true;
+        }
+        if let Some(clean_appdir) = self.clean_appdir {
+            config.clean_appdir = clean_appdir;
+        }
+        if self.bundle_manifest {
+            config.bundle_manifest = true;
+        }
+        if self.embed_build_info {
+            config.embed_build_info = true;
         }
+        config.quiet = self.quiet;
+        config.list_libs = self.list_libs;
+        config.force = self.force;
+        config.no_build = self.no_build;
+        config.fetch_appimagetool = self.fetch_appimagetool;
+        config.squashfs_only = self.squashfs_only;
+        if self.prefix_package {
+            config.prefix_package = true;
+        }
+        config.binary = self.binary.clone();
+        config.staging_dir = self.staging_dir.clone();
+        // Several targets replace the ones from the manifest and are built one by one
+        match &self.targets[..] {
+            [] => {}
+            [target] => config.target = Some(target.clone()),
+            targets => config.targets = targets.to_vec(),
+        }
+        config.cargo_args = self.cargo_args.clone();
+        Ok(config)
     }
-    Ok(libs)
 }
 
-fn main() -> Result<()> {
-    let (path, meta) = get_manifest()?;
-    let path = path.canonicalize().context("Could not canonicalize path")?;
-    println!("Found manifest: {path:?}");
-    let parent = path.parent().context("Package path has no parent")?;
-    println!("Moving into package root: {parent:?}");
-    std::env::set_current_dir(parent).context("Could not chdir to package root")?;
-    let pkg = meta
-        .package
-        .context(format!("Cannot load metadata from {CARGO_FNAME}"))?;
+fn main() {
+    let result = run().and_then(|()| match diag::denied_warnings() {
+        0 => Ok(()),
+        1 => Err(anyhow!("1 warning was printed").context(Failure::Warnings)),
+        warnings => Err(anyhow!("{warnings} warnings were printed").context(Failure::Warnings)),
+    });
+    if let Err(e) = result {
+        diag::error(format_args!("{e:?}"));
+        std::process::exit(Failure::exit_code_of(&e));
+    }
+}
 
-    // Create and execute cargo build command.
-    let mut command = Command::new("cargo");
-    command.arg("build");
-    if !std::env::args()
-        .skip(2)
-        .any(|arg| arg.starts_with("--profile="))
-    {
-        command.arg("--release");
+fn run() -> Result<()> {
+    let mut args = std::env::args().skip(2).peekable();
+    if args.peek().map(String::as_str) == Some("init") {
+        let cli = CliArgs::parse(args.skip(1)).context(Failure::Config)?;
+        return init_manifest(cli.package_path().context(Failure::Config)?);
     }
-    command.args(std::env::args().skip(2));
-    let status = command.status().context("Failed to build package")?;
-    if !status.success() {
-        bail!("Failed to build package");
+    let cli = CliArgs::parse(args).context(Failure::Config)?;
+    if cli.json_messages {
+        diag::disable_colors();
+        diag::messages_to_stderr();
+    }
+    let package_path = cli.package_path().context(Failure::Config)?;
+    if !cli.workspace {
+        let config = cli.config(&package_path)?;
+        if cli.print_config {
+            print!("{}", config.to_toml());
+        } else if cli.check {
+            check_config(config)?;
+        } else if cli.print_path {
+            let stdout = redirect_stdout()?;
+            print_paths(stdout, &build_appimage(config)?)?;
+        } else {
+            build_appimage(config)?;
+        }
+        return Ok(());
+    }
+    if cli.binary.is_some() {
+        bail!("--binary cannot be used with --workspace, it packages a single binary");
+    }
+    if cli.appimage_name.is_some() {
+        bail!("--appimage-name cannot be used with --workspace, every AppImage would get the same name");
+    }
+    if cli.print_config {
+        for manifest in workspace_members(&package_path, &cli.cargo_args)? {
+            println!(
+                "# {}\n{}",
+                manifest.display(),
+                cli.config(&manifest)?.to_toml()
+            );
+        }
+        return Ok(());
+    }
+    if cli.check {
+        // Every member is checked so all problems are reported at once
+        let mut failed = 0;
+        for manifest in workspace_members(&package_path, &cli.cargo_args)? {
+            if let Err(e) = cli.config(&manifest).and_then(check_config) {
+                diag::error(format_args!("{e:?}"));
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(
+                anyhow!("{failed} workspace members have problems").context(Failure::Config)
+            );
+        }
+        return Ok(());
     }
 
-    let cargo_metadata = cargo_metadata::MetadataCommand::new()
-        .exec()
-        .context("Failed to execute cargo metadata")?;
-    let target_prefix = cargo_metadata.target_directory;
-    let target_stage_dir = PathBuf::from(target_prefix.clone()).join("appimage_build");
-    fs_extra::dir::create_all(&target_stage_dir, true)
-        .with_context(|| format!("Error creating {}", target_stage_dir.display()))?;
-
-    let assets;
-    let target = {
-        let profile = std::env::args()
-            .skip(2)
-            .find(|arg| arg.starts_with("--profile="))
-            .map(|arg| arg.split_at(10).1.to_string())
-            .unwrap_or_else(|| "release".into());
-        std::env::args()
-            .skip(2)
-            .find(|arg| arg.starts_with("--target="))
-            .map(|arg| format!("{}/{}", arg.split_at(9).1, profile))
-            .unwrap_or_else(|| profile)
+    let stdout = if cli.print_path {
+        Some(redirect_stdout()?)
+    } else {
+        None
     };
-    let link_deps;
-    let mut link_exclude_list = Vec::with_capacity(0);
-    let mut args = vec![];
-
-    if let Some(meta) = pkg.metadata.as_ref() {
-        match meta {
-            Value::Table(t) => match t.get("appimage") {
-                Some(Value::Table(t)) => {
-                    match t.get("assets") {
-                        Some(Value::Array(v)) => {
-                            assets = v
-                                .iter()
-                                .filter_map(|v| match v {
-                                    Value::String(s) => Some(s),
-                                    _ => None,
-                                })
-                                .collect()
-                        }
-                        _ => assets = Vec::with_capacity(0),
-                    }
-                    match t.get("auto_link") {
-                        Some(Value::Boolean(v)) => link_deps = v.to_owned(),
-                        _ => link_deps = false,
-                    }
-                    if let Some(Value::Array(v)) = t.get("args") {
-                        args = v
-                            .iter()
-                            .filter_map(|v| match v {
-                                Value::String(s) => Some(s),
-                                _ => None,
-                            })
-                            .collect()
-                    }
-                    if let Some(Value::Array(arr)) = t.get("auto_link_exclude_list") {
-                        for v in arr.iter() {
-                            if let Value::String(s) = v {
-                                link_exclude_list.push(glob::Pattern::new(s).context(
-                                    "Auto-link exclude list item not a valid glob pattern",
-                                )?);
-                            }
-                        }
-                    }
+    let members = workspace_members(&package_path, &cli.cargo_args)?;
+    check_collisions(&cli, &members)?;
+    let mut outputs = vec![];
+    let mut failed = 0;
+    // The failure with the highest exit code decides the one of the whole run
+    let mut worst: Option<Failure> = None;
+    for manifest in members {
+        let result = cli
+            .config(&manifest)
+            .and_then(build_appimage)
+            .with_context(|| format!("Could not package {}", manifest.display()));
+        match result {
+            Ok(built) => outputs.extend(built),
+            Err(e) if cli.keep_going => {
+                diag::error(format_args!("{e:?}"));
+                failed += 1;
+                if let Some(&failure) = e.downcast_ref::<Failure>() {
+                    worst = match worst {
+                        Some(worst) if worst.exit_code() >= failure.exit_code() => Some(worst),
+                        _ => Some(failure),
+                    };
                 }
-                _ => {
-                    assets = Vec::with_capacity(0);
-                    link_deps = false
-                }
-            },
-            _ => {
-                assets = Vec::with_capacity(0);
-                link_deps = false
             }
-        };
-    } else {
-        assets = Vec::with_capacity(0);
-        link_deps = false;
+            Err(e) => return Err(e),
+        }
+    }
+    if let Some(stdout) = stdout {
+        // The AppImages of the members that were packaged are useful on their own
+        print_paths(stdout, &outputs)?;
+    }
+    if failed > 0 {
+        let error = anyhow!("{failed} workspace members could not be packaged");
+        return Err(match worst {
+            Some(failure) => error.context(failure),
+            None => error,
+        });
     }
+    Ok(())
+}
 
-    for currentbin in meta.bin {
-        let name = currentbin.name.unwrap_or(pkg.name.clone());
-        let appdirpath = std::path::Path::new(&target_prefix).join(name.clone() + ".AppDir");
-        fs_extra::dir::create_all(appdirpath.join("usr"), true)
-            .with_context(|| format!("Error creating {}", appdirpath.join("usr").display()))?;
+/// Print the absolute path of every AppImage on its own line for `--print-path`
+fn print_paths(mut stdout: File, outputs: &[PathBuf]) -> Result<()> {
+    for output in outputs {
+        let output = output.canonicalize().unwrap_or_else(|_| output.clone());
+        writeln!(stdout, "{}", output.display()).context("Could not print the AppImage paths")?;
+    }
+    Ok(())
+}
 
-        fs_extra::dir::create_all(appdirpath.join("usr/bin"), true)
-            .with_context(|| format!("Error creating {}", appdirpath.join("usr/bin").display()))?;
+/// Send everything written to stdout from now on, including the output of cargo and the
+/// other tools run, to stderr and return the original stdout for `--print-path`
+fn redirect_stdout() -> Result<File> {
+    // SAFETY: only file descriptors 1 and 2 are touched, and the duplicate of 1 is owned
+    // by the returned file alone
+    unsafe {
+        let stdout = libc::dup(libc::STDOUT_FILENO);
+        if stdout < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(std::io::Error::last_os_error()).context("Could not redirect stdout");
+        }
+        Ok(File::from_raw_fd(stdout))
+    }
+}
 
-        let lib_dir_staged = appdirpath.join("libs");
-        if link_deps {
-            stage_libs(
-                &lib_dir_staged,
-                &PathBuf::from(&target_prefix),
-                &target,
-                &name,
-            )
-            .context("Could not stage libs")?;
+/// Fail before anything is built when the AppImages of different workspace `members`
+/// would be written to the same path, e.g. for binaries of the same name
+fn check_collisions(cli: &CliArgs, members: &[PathBuf]) -> Result<()> {
+    let mut outputs: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for manifest in members {
+        // Members that cannot be planned fail on their own once they are packaged
+        let Ok(plans) = cli.config(manifest).and_then(|config| plan(&config)) else {
+            continue;
+        };
+        for appimage in plans {
+            outputs.entry(appimage.output).or_default().push(format!(
+                "{} of {}",
+                appimage.name,
+                manifest.display()
+            ));
         }
+    }
+    let collisions: Vec<String> = outputs
+        .iter()
+        .filter(|(_, binaries)| binaries.len() > 1)
+        .map(|(output, binaries)| {
+            format!(
+                "{} would be created for {}",
+                output.display(),
+                binaries.join(" and ")
+            )
+        })
+        .collect();
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{}\nPass --prefix-package to name the AppImages <package>-<binary>, or set prefix_package, product_name or exclude_bins for the members",
+        collisions.join("\n")
+    )
+    .context(Failure::Config))
+}
 
-        if lib_dir_staged.exists() {
-            for i in std::fs::read_dir(&lib_dir_staged).context("Could not read libs dir")? {
-                let path = &i?.path();
+/// Get the value of a cargo-appimage flag given either as `--flag=value` or as
+/// `--flag value`, returning `None` when `arg` is a different flag
+fn flag_value(
+    arg: &str,
+    flag: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Option<Result<String>> {
+    let rest = arg.strip_prefix(flag)?;
+    if let Some(value) = rest.strip_prefix('=') {
+        Some(Ok(value.to_string()))
+    } else if rest.is_empty() {
+        Some(
+            args.next()
+                .with_context(|| format!("{flag} expects a value")),
+        )
+    } else {
+        None
+    }
+}
 
-                // Skip if it matches the exclude list.
-                if let Some(file_name) = path.file_name().and_then(|p| p.to_str()) {
-                    if link_exclude_list.iter().any(|p| p.matches(file_name)) {
-                        continue;
-                    }
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                let link = std::fs::read_link(path)
-                    .with_context(|| format!("Error reading link in libs {}", path.display()))?;
+    fn parse(args: &[&str]) -> Result<CliArgs> {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
 
-                fs_extra::dir::create_all(
-                    appdirpath.join(
-                        &link
-                            .parent()
-                            .with_context(|| format!("Lib {} has no parent dir", &link.display()))?
-                            .to_str()
-                            .with_context(|| format!("{} is not valid Unicode", link.display()))?
-                            [1..],
-                    ),
-                    false,
-                )?;
-                let dest = appdirpath.join(
-                    &link
-                        .to_str()
-                        .with_context(|| format!("{} is not valid Unicode", link.display()))?[1..],
-                );
-                std::fs::copy(&link, &dest).with_context(|| {
-                    format!("Error copying {} to {}", &link.display(), dest.display())
-                })?;
-            }
-        }
+    #[test]
+    fn flag_values() {
+        let mut rest = vec!["5".to_string()].into_iter();
+        let value = |arg| flag_value(arg, "--tool-timeout", &mut std::iter::empty());
+        assert_eq!(value("--tool-timeout=5").unwrap().unwrap(), "5");
+        assert!(value("--tool-timeout").unwrap().is_err());
+        assert!(value("--tool-timeouts=5").is_none());
+        assert!(value("--tool-retries=5").is_none());
+        assert_eq!(
+            flag_value("--tool-timeout", "--tool-timeout", &mut rest)
+                .unwrap()
+                .unwrap(),
+            "5"
+        );
+        assert!(rest.next().is_none());
+    }
 
-        std::fs::copy(
-            format!("{}/{}/{}", target_prefix, &target, &name),
-            appdirpath.join(format!("usr/bin/{}", &name)),
-        )
-        .with_context(|| {
-            format!(
-                "Cannot find binary file at {}/{}/{}",
-                target_prefix, &target, &name
-            )
-        })?;
+    #[test]
+    fn tool_timeout_must_be_positive() {
+        let cli = parse(&["--tool-timeout", "300"]).unwrap();
+        assert_eq!(cli.tool_timeout, Some(Duration::from_secs(300)));
+        assert!(parse(&["--tool-timeout=0"]).is_err());
+        assert!(parse(&["--tool-timeout=-1"]).is_err());
+    }
 
-        let icon_path = std::path::Path::new("./icon.png");
-        let icon_dest_path = appdirpath.join(icon_path.file_name().unwrap());
-        if icon_path.is_file() {
-            std::fs::copy(icon_path, &icon_dest_path)
-                .context(format!("Cannot copy {icon_path:?}"))?;
-        } else {
-            std::fs::write(&icon_dest_path, [])
-                .context(format!("Failed to generate {icon_dest_path:?}"))?;
-        }
-        fs_extra::copy_items(
-            &assets,
-            appdirpath.as_path(),
-            &CopyOptions {
-                overwrite: true,
-                buffer_size: 0,
-                copy_inside: true,
-                ..Default::default()
-            },
-        )
-        .context("Error copying assets")?;
-        std::fs::write(
-            appdirpath.join("cargo-appimage.desktop"),
-            format!(
-                "[Desktop Entry]\nName={}\nExec={}\nIcon=icon\nType=Application\nCategories=Utility;", name
-                , name),
-                )
-            .with_context(|| {
-                format!(
-                    "Error writing desktop file {}",
-                    appdirpath.join("cargo-appimage.desktop").display()
-                    )
-            })?;
-        let app_runner_path = get_app_runner_binary_path()?;
-        std::fs::copy(&app_runner_path, appdirpath.join("AppRun")).with_context(|| {
-            format!(
-                "Error copying {} to {}",
-                app_runner_path.display(),
-                appdirpath.join("AppRun").display()
-            )
-        })?;
+    #[test]
+    fn network_flags_reach_cargo() {
+        let cli = parse(&["--offline", "--frozen", "--locked", "--force"]).unwrap();
+        assert_eq!(cli.cargo_args, ["--offline", "--frozen", "--locked"]);
+        assert!(cli.force);
+    }
 
-        let mut bin_args = args.to_vec();
-        let appdirpath = appdirpath.into_os_string().into_string().unwrap();
-        bin_args.push(&appdirpath);
+    #[test]
+    fn profile_and_targets() {
+        let cli = parse(&[
+            "--profile",
+            "dist",
+            "--target=x86_64-unknown-linux-gnu",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "--bin=app",
+        ])
+        .unwrap();
+        assert_eq!(cli.profile.as_deref(), Some("dist"));
+        assert_eq!(
+            cli.targets,
+            ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"]
+        );
+        assert_eq!(cli.cargo_args, ["--bin=app"]);
+    }
 
-        std::fs::create_dir_all(format!("{}/appimage", &target_prefix))
-            .context("Unable to create output dir")?;
-        Command::new("appimagetool")
-            .args(bin_args)
-            .arg(format!("{}/appimage/{}.AppImage", &target_prefix, &name))
-            .env("ARCH", platforms::target::TARGET_ARCH.as_str())
-            .env("VERSION", pkg.version())
-            .status()
-            .context("Error occurred: make sure that appimagetool is installed")?;
+    #[test]
+    fn repeated_profile_is_rejected() {
+        assert!(parse(&["--profile=dev", "--profile", "release"]).is_err());
+        assert!(parse(&["--release", "--debug"]).is_err());
     }
 
-    Ok(())
+    #[test]
+    fn json_messages_are_quiet() {
+        let cli = parse(&["--message-format=json-render-diagnostics"]).unwrap();
+        assert!(cli.json_messages);
+        assert!(cli.quiet);
+        assert_eq!(cli.cargo_args, ["--message-format=json-render-diagnostics"]);
+    }
 }
@@ -0,0 +1,26 @@
+use anyhow::{Context, Result};
+use std::{fs::Permissions, os::unix::fs::PermissionsExt, path::Path};
+
+/// Give `dir` and everything inside it the same permissions regardless of the umask of
+/// the build user: 0755 for directories and executables, 0644 for everything else.
+/// Symlinks are left alone.
+pub(crate) fn normalize(dir: &Path) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Could not read {}", dir.display()))?
+    {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            normalize(&entry.path())?;
+        } else if file_type.is_file() {
+            let executable = entry.metadata()?.permissions().mode() & 0o111 != 0;
+            set_mode(&entry.path(), if executable { 0o755 } else { 0o644 })?;
+        }
+    }
+    set_mode(dir, 0o755)
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    std::fs::set_permissions(path, Permissions::from_mode(mode))
+        .with_context(|| format!("Could not set the permissions of {}", path.display()))
+}
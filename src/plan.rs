@@ -0,0 +1,86 @@
+//! What a build would produce, worked out without building anything
+
+use crate::{
+    appimages_of, arch, get_manifest_from_path, icon, metadata_command, output_path, output_root,
+    AppImageConfig, Failure, CARGO_FNAME,
+};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// One AppImage [`plan`] expects a build to create
+#[derive(Debug, Clone)]
+pub struct BinaryPlan {
+    /// Binary the AppImage is named after and starts
+    pub name: String,
+    /// Every binary packaged in the AppImage, including `name`
+    pub binaries: Vec<String>,
+    /// Where the AppImage is written
+    pub output: PathBuf,
+    /// Icon that is packaged, `None` when the default or an empty icon is used instead
+    pub icon: Option<PathBuf>,
+    /// Categories of the generated desktop entry
+    pub categories: Vec<String>,
+    /// Settings the AppImage is built with, the `bin.<name>` table of `name` applied
+    pub config: AppImageConfig,
+}
+
+/// List the AppImages [`build_appimage`](crate::build_appimage) would create for `config`
+/// with the settings each of them is built with. Only `cargo metadata` is run, for the
+/// target directory.
+pub fn plan(config: &AppImageConfig) -> Result<Vec<BinaryPlan>> {
+    if config.target.is_none() && !config.targets.is_empty() {
+        let mut plans = vec![];
+        for target in &config.targets {
+            let mut config = config.clone();
+            config.target = Some(target.clone());
+            config.name_with_arch = config.targets.len() > 1;
+            plans.extend(plan(&config)?);
+        }
+        return Ok(plans);
+    }
+
+    let (path, meta) = get_manifest_from_path(&config.package_path).context(Failure::Config)?;
+    let path = path.canonicalize().context("Could not canonicalize path")?;
+    let root = path.parent().context("Package path has no parent")?;
+    let pkg = meta
+        .package
+        .as_ref()
+        .context(format!("Cannot load metadata from {CARGO_FNAME}"))?;
+    let (_, _, appimages) = appimages_of(config, pkg, &meta.bin).context(Failure::Config)?;
+
+    let cargo_metadata = metadata_command(&config.cargo_args)
+        .manifest_path(&path)
+        .exec()
+        .context("Failed to execute cargo metadata")
+        .context(Failure::Metadata)?;
+    let out_dir = output_root(config, Path::new(&cargo_metadata.target_directory)).join("appimage");
+    let appimage_arch = arch::packaged_arch(
+        config.arch_override.as_deref(),
+        arch::build_target(
+            config.target.as_deref(),
+            std::env::var("CARGO_BUILD_TARGET").ok(),
+        )
+        .as_deref(),
+    );
+
+    let mut plans = vec![];
+    for (name, binaries) in appimages {
+        let mut config = config.for_bin(&name)?;
+        config.resolve_paths(root).context(Failure::Config)?;
+        let icon = icon::find(root, &name, config.icon.as_deref())?;
+        let categories = if config.categories.is_empty() {
+            vec!["Utility".to_string()]
+        } else {
+            config.categories.clone()
+        };
+        plans.push(BinaryPlan {
+            output: output_path(&config, &pkg.name, &name, &out_dir, &appimage_arch),
+            name,
+            binaries,
+            icon,
+            categories,
+            config,
+        });
+    }
+    Ok(plans)
+}
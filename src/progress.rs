@@ -0,0 +1,54 @@
+use crate::diag;
+use std::io::{IsTerminal, Write};
+
+/// How many items are handled between two progress lines when stdout is not a terminal
+const LINE_INTERVAL: usize = 10;
+
+/// Counter for long running steps, redrawn in place on a terminal and printed every few
+/// items otherwise
+pub(crate) struct Progress {
+    label: String,
+    total: usize,
+    done: usize,
+    enabled: bool,
+    tty: bool,
+}
+
+impl Progress {
+    pub(crate) fn new(label: impl Into<String>, total: usize, enabled: bool) -> Self {
+        Progress {
+            label: label.into(),
+            total,
+            done: 0,
+            enabled: enabled && total > 0,
+            tty: if diag::to_stderr() {
+                std::io::stderr().is_terminal()
+            } else {
+                std::io::stdout().is_terminal()
+            },
+        }
+    }
+
+    pub(crate) fn inc(&mut self) {
+        self.done += 1;
+        if !self.enabled {
+            return;
+        }
+        let line = format!("{} {}/{}", self.label, self.done, self.total);
+        if self.tty {
+            // Progress goes wherever the other messages for people do
+            let mut out: Box<dyn Write> = if diag::to_stderr() {
+                Box::new(std::io::stderr())
+            } else {
+                Box::new(std::io::stdout())
+            };
+            let _ = write!(out, "\r{line}");
+            if self.done == self.total {
+                let _ = writeln!(out);
+            }
+            let _ = out.flush();
+        } else if self.done.is_multiple_of(LINE_INTERVAL) || self.done == self.total {
+            diag::info(line);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::{File, FileTimes},
+    path::Path,
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+/// Pick the timestamp used for a reproducible build.
+///
+/// In order: the configured epoch, `SOURCE_DATE_EPOCH` from the environment and the
+/// time of the latest git commit.
+pub(crate) fn source_date_epoch(configured: Option<u64>) -> Result<u64> {
+    if let Some(epoch) = configured {
+        return Ok(epoch);
+    }
+    if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+        return epoch
+            .trim()
+            .parse()
+            .with_context(|| format!("SOURCE_DATE_EPOCH {epoch:?} is not a number"));
+    }
+
+    let output = Command::new("git")
+        .args(["log", "-1", "--format=%ct"])
+        .output()
+        .context("Could not run git to get the latest commit time")?;
+    if !output.status.success() {
+        bail!(
+            "Could not get the latest commit time, set SOURCE_DATE_EPOCH or pass --reproducible=<epoch>\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .context("git returned an invalid commit time")
+}
+
+/// Set the mtime of `dir` and everything inside it to `epoch`, symlinks are left alone
+pub(crate) fn normalize_mtimes(dir: &Path, epoch: u64) -> Result<()> {
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(epoch);
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Could not read {}", dir.display()))?
+    {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            normalize_mtimes(&entry.path(), epoch)?;
+        } else if file_type.is_file() {
+            set_mtime(&entry.path(), time)?;
+        }
+    }
+    set_mtime(dir, time)
+}
+
+fn set_mtime(path: &Path, time: SystemTime) -> Result<()> {
+    File::open(path)
+        .and_then(|f| f.set_times(FileTimes::new().set_accessed(time).set_modified(time)))
+        .with_context(|| format!("Could not set the timestamps of {}", path.display()))
+}
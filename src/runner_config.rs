@@ -0,0 +1,67 @@
+//! Settings handed from cargo-appimage to the AppRun runner through a file in the AppDir
+
+use std::collections::BTreeMap;
+
+/// Name of the runner config file in the root of the AppDir
+pub const FILE_NAME: &str = ".cargo-appimage-runner";
+
+/// Settings read by `cargo-appimage-runner` when the AppImage starts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunnerConfig {
+    /// AppDir relative directory holding the bundled libraries
+    pub lib_dir: Option<String>,
+    /// AppDir relative path of the splash image
+    pub splash: Option<String>,
+    /// Env variables set before starting the binary
+    pub env: BTreeMap<String, String>,
+    /// Executable in `usr/bin` started by default
+    pub exec: Option<String>,
+    /// Show the end of stderr in a dialog and log it when the executable fails
+    pub error_dialog: bool,
+}
+
+impl RunnerConfig {
+    /// Parse the `key=value` lines of a runner config file, unknown keys are ignored
+    pub fn parse(contents: &str) -> Self {
+        let mut config = RunnerConfig::default();
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "lib_dir" => config.lib_dir = Some(value.to_string()),
+                "splash" => config.splash = Some(value.to_string()),
+                "exec" => config.exec = Some(value.to_string()),
+                "error_dialog" => config.error_dialog = value == "true",
+                "env" => {
+                    if let Some((name, value)) = value.split_once('=') {
+                        config.env.insert(name.to_string(), value.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        config
+    }
+
+    /// Render the config in the format read by [`RunnerConfig::parse`]
+    pub fn render(&self) -> String {
+        let mut contents = String::new();
+        if let Some(lib_dir) = &self.lib_dir {
+            contents += &format!("lib_dir={lib_dir}\n");
+        }
+        if let Some(splash) = &self.splash {
+            contents += &format!("splash={splash}\n");
+        }
+        if let Some(exec) = &self.exec {
+            contents += &format!("exec={exec}\n");
+        }
+        if self.error_dialog {
+            contents += "error_dialog=true\n";
+        }
+        for (name, value) in &self.env {
+            contents += &format!("env={name}={value}\n");
+        }
+        contents
+    }
+}
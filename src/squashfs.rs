@@ -0,0 +1,59 @@
+//! Bare squashfs images of the AppDir, for AppImages put together with a custom runtime
+
+use crate::Failure;
+use anyhow::{anyhow, Context, Result};
+use std::{io::ErrorKind, path::Path, process::Command};
+
+/// Create the squashfs image `output` of `appdir` with mksquashfs, using the compression
+/// picked with `--comp` in the appimagetool `args` and passing `mksquashfs_args` on
+pub(crate) fn create(
+    appdir: &Path,
+    output: &Path,
+    args: &[String],
+    mksquashfs_args: &[String],
+    source_date_epoch: Option<u64>,
+) -> Result<()> {
+    let mut command = Command::new("mksquashfs");
+    // The same options appimagetool creates the filesystem with
+    command
+        .arg(appdir)
+        .arg(output)
+        .args(["-root-owned", "-noappend"]);
+    if let Some(comp) = compression(args) {
+        command.arg("-comp").arg(comp);
+    }
+    command.args(mksquashfs_args);
+    if let Some(epoch) = source_date_epoch {
+        command.env("SOURCE_DATE_EPOCH", epoch.to_string());
+    }
+    let status = match command.status() {
+        Ok(status) => status,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(e)
+                .context("mksquashfs is not installed, it is usually packaged as squashfs-tools")
+                .context(Failure::MissingTool)
+        }
+        Err(e) => {
+            return Err(e)
+                .context("Could not run mksquashfs")
+                .context(Failure::Appimagetool)
+        }
+    };
+    if !status.success() {
+        return Err(anyhow!("mksquashfs failed with {status}").context(Failure::Appimagetool));
+    }
+    Ok(())
+}
+
+/// Compression given to appimagetool as `--comp <comp>` or `--comp=<comp>`
+fn compression(args: &[String]) -> Option<&str> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(comp) = arg.strip_prefix("--comp=") {
+            return Some(comp);
+        } else if arg == "--comp" {
+            return args.next().map(String::as_str);
+        }
+    }
+    None
+}
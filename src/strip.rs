@@ -0,0 +1,175 @@
+use crate::diag;
+use anyhow::{bail, Context, Result};
+use std::{path::Path, process::Command};
+
+/// What a cargo profile strips from the binaries it builds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProfileStrip {
+    None,
+    Debuginfo,
+    Symbols,
+}
+
+impl ProfileStrip {
+    fn parse(value: &toml::Value) -> Option<Self> {
+        match value {
+            toml::Value::Boolean(true) => Some(Self::Symbols),
+            toml::Value::Boolean(false) => Some(Self::None),
+            toml::Value::String(s) => Self::parse_str(s),
+            _ => None,
+        }
+    }
+
+    fn parse_str(value: &str) -> Option<Self> {
+        match value {
+            "true" | "symbols" => Some(Self::Symbols),
+            "false" | "none" => Some(Self::None),
+            "debuginfo" => Some(Self::Debuginfo),
+            _ => None,
+        }
+    }
+}
+
+/// The `strip` setting cargo uses for `profile`, from `CARGO_PROFILE_<NAME>_STRIP` or the
+/// `[profile]` tables of the workspace root manifest, following `inherits`
+pub(crate) fn profile_strip(root_manifest: &Path, profile: &str) -> Result<ProfileStrip> {
+    let env = |key: &str| {
+        format!(
+            "CARGO_PROFILE_{}_{key}",
+            profile.to_uppercase().replace('-', "_")
+        )
+    };
+    if let Ok(value) = std::env::var(env("STRIP")) {
+        return ProfileStrip::parse_str(&value)
+            .with_context(|| format!("{} has invalid value {value:?}", env("STRIP")));
+    }
+
+    let contents = std::fs::read_to_string(root_manifest)
+        .with_context(|| format!("Could not read {}", root_manifest.display()))?;
+    let manifest: toml::Table = contents
+        .parse()
+        .with_context(|| format!("Could not parse {}", root_manifest.display()))?;
+    let profiles = manifest.get("profile").and_then(toml::Value::as_table);
+    let debug = std::env::var(env("DEBUG"))
+        .ok()
+        .map(|value| debuginfo(&toml::Value::String(value)));
+    resolve_strip(profiles, profile, debug)
+}
+
+/// The `strip` setting of `profile` in `profiles`, or cargo's default for it: since cargo 1.77
+/// profiles without debuginfo strip it, like the built-in `release` profile
+fn resolve_strip(
+    profiles: Option<&toml::Table>,
+    profile: &str,
+    mut debug: Option<bool>,
+) -> Result<ProfileStrip> {
+    let mut name = profile.to_string();
+    // A handful of steps is plenty, cargo rejects inheritance cycles anyway
+    for _ in 0..8 {
+        let table = profiles
+            .and_then(|p| p.get(&name))
+            .and_then(toml::Value::as_table);
+        if let Some(strip) = table.and_then(|t| t.get("strip")) {
+            return ProfileStrip::parse(strip)
+                .with_context(|| format!("Invalid strip setting in profile {name}"));
+        }
+        if debug.is_none() {
+            debug = table.and_then(|t| t.get("debug")).map(debuginfo);
+        }
+        match table
+            .and_then(|t| t.get("inherits"))
+            .and_then(toml::Value::as_str)
+            .or(match name.as_str() {
+                "test" => Some("dev"),
+                "bench" => Some("release"),
+                _ => None,
+            }) {
+            Some(parent) => name = parent.to_string(),
+            None => break,
+        }
+    }
+    // Of the built-in profiles only dev and the test profile inheriting it build debuginfo
+    if debug.unwrap_or(name == "dev") {
+        Ok(ProfileStrip::None)
+    } else {
+        Ok(ProfileStrip::Debuginfo)
+    }
+}
+
+/// Whether a `debug` setting builds debuginfo
+fn debuginfo(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Boolean(b) => *b,
+        toml::Value::Integer(i) => *i != 0,
+        toml::Value::String(s) => !matches!(s.as_str(), "none" | "false" | "0"),
+        _ => true,
+    }
+}
+
+/// Strip the symbols of the binary at `path`, unless `profile` already did
+pub(crate) fn strip(path: &Path, profile: ProfileStrip) -> Result<()> {
+    match profile {
+        ProfileStrip::Symbols => {
+            diag::note("Not stripping, the profile already strips symbols");
+            return Ok(());
+        }
+        ProfileStrip::Debuginfo => {
+            diag::note("The profile only strips debuginfo, stripping the symbols as well")
+        }
+        ProfileStrip::None => {}
+    }
+    let status = Command::new("strip")
+        .arg(path)
+        .status()
+        .context("Could not run strip, make sure binutils are installed")?;
+    if !status.success() {
+        bail!("strip failed on {} with {status}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_of(manifest: &str, profile: &str) -> ProfileStrip {
+        let manifest: toml::Table = manifest.parse().unwrap();
+        let profiles = manifest.get("profile").and_then(toml::Value::as_table);
+        resolve_strip(profiles, profile, None).unwrap()
+    }
+
+    #[test]
+    fn release_strips_debuginfo_by_default() {
+        assert_eq!(strip_of("", "release"), ProfileStrip::Debuginfo);
+        assert_eq!(strip_of("", "bench"), ProfileStrip::Debuginfo);
+        assert_eq!(
+            strip_of("[profile.release]\ndebug = true", "release"),
+            ProfileStrip::None
+        );
+        assert_eq!(
+            strip_of("[profile.release]\nstrip = true", "release"),
+            ProfileStrip::Symbols
+        );
+        assert_eq!(
+            strip_of("[profile.dist]\ninherits = \"release\"", "dist"),
+            ProfileStrip::Debuginfo
+        );
+    }
+
+    #[test]
+    fn dev_keeps_debuginfo_by_default() {
+        assert_eq!(strip_of("", "dev"), ProfileStrip::None);
+        assert_eq!(strip_of("", "test"), ProfileStrip::None);
+        assert_eq!(
+            strip_of("[profile.dev]\ndebug = \"none\"", "dev"),
+            ProfileStrip::Debuginfo
+        );
+        assert_eq!(
+            strip_of(
+                "[profile.dev]\ndebug = 0\n[profile.fast]\ninherits = \"dev\"\ndebug = 1",
+                "fast"
+            ),
+            ProfileStrip::None
+        );
+    }
+}
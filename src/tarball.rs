@@ -0,0 +1,43 @@
+//! Compressed tarballs of the AppDir, for systems that cannot run AppImages
+
+use crate::Failure;
+use anyhow::{anyhow, Context, Result};
+use std::{io::ErrorKind, path::Path, process::Command};
+
+/// Create the gzip compressed tarball `output` of `appdir` with tar. The AppDir is the
+/// top directory of the archive, permissions and symlinks are kept as they are.
+pub(crate) fn create(appdir: &Path, output: &Path, source_date_epoch: Option<u64>) -> Result<()> {
+    let (parent, name) = appdir
+        .parent()
+        .zip(appdir.file_name())
+        .with_context(|| format!("{} has no parent directory", appdir.display()))?;
+    let mut command = Command::new("tar");
+    command.arg("-czf").arg(output).arg("-C").arg(parent);
+    if let Some(epoch) = source_date_epoch {
+        // GNU tar options, the same files then always give the same archive
+        command.arg(format!("--mtime=@{epoch}")).args([
+            "--sort=name",
+            "--owner=0",
+            "--group=0",
+            "--numeric-owner",
+        ]);
+    }
+    command.arg("--").arg(name);
+    let status = match command.status() {
+        Ok(status) => status,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(e)
+                .context("tar is not installed, it is needed for emit_tarball")
+                .context(Failure::MissingTool)
+        }
+        Err(e) => {
+            return Err(e)
+                .context("Could not run tar")
+                .context(Failure::Staging)
+        }
+    };
+    if !status.success() {
+        return Err(anyhow!("tar failed with {status}").context(Failure::Staging));
+    }
+    Ok(())
+}
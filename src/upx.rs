@@ -0,0 +1,20 @@
+use crate::diag;
+use anyhow::{bail, Context, Result};
+use std::{io::ErrorKind, path::Path, process::Command};
+
+/// Compress the binary at `path` in place with UPX, passing `args` before it. Missing UPX
+/// only skips the compression since the AppImage works without it.
+pub(crate) fn compress(path: &Path, args: &[String]) -> Result<()> {
+    let status = match Command::new("upx").args(args).arg(path).status() {
+        Ok(status) => status,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            diag::warning("upx is not installed, the binary is not compressed");
+            return Ok(());
+        }
+        Err(e) => return Err(e).context("Could not run upx"),
+    };
+    if !status.success() {
+        bail!("upx failed on {} with {status}", path.display());
+    }
+    Ok(())
+}